@@ -40,27 +40,6 @@ case "$PKG_MANAGER" in
         ;;
 esac
 
-echo "Installing rustup..."
-curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y
-
-echo 'export PATH="$HOME/.cargo/bin:$PATH"' >> $HOME/.bashrc
-echo 'export PATH="$HOME/.cargo/bin:$PATH"' >> $HOME/.zshrc
-
-source $HOME/.cargo/env
-
-echo "Updating rustup and installing the stable version of Rust..."
-rustup update stable
-
-echo "Checking Rust installation..."
-if command -v rustc &> /dev/null
-then
-    echo "Rust is successfully installed"
-    rustc --version
-else
-    echo -e "\033[0;31mRust installation failed\033[0m"
-    exit 1
-fi
-
 echo "Downloading the latest release from the hiw-lang repository..."
 LATEST_RELEASE_URL=$(curl -s https://api.github.com/repos/mealet/hiw-lang/releases/latest | grep "browser_download_url" | grep "hiw-release.zip" | cut -d '"' -f 4)
 if [ -n "$LATEST_RELEASE_URL" ]; then
@@ -7,118 +7,120 @@
 // ----------------------------------------
 
 // Binary Compiler - module, which created to wrap virtual-machine and compiled byte-code to
-// executable file
+// executable file.
+//
+// Executable is a copy of running hiw with bytecode appended to the end:
+//
+//     hiw executable | bytecode | u64 bytecode length | "HIWEXEC!"
+//
+// On startup hiw checks own file for this trailer and runs the bytecode instead of CLI.
 
-use crate::{vm::Operations, vm::Value};
+use crate::{
+    bytecode::{self, FormatError},
+    compiler::ByteCode,
+};
 use colored::Colorize;
-use std::{fmt, io::Write};
-
-impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Value::INT(i) => write!(f, "Value::INT({})", i),
-            Value::STR(s) => write!(f, "Value::STR(\"{}\".to_string())", s),
-            Value::BOOL(b) => write!(f, "Value::BOOL({})", b),
-            Value::ARRAY(a) => write!(f, "Value::ARRAY({:?})", a),
-        }
-    }
-}
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+};
 
-impl fmt::Display for Operations {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s = match self {
-            Operations::PUSH => "Operations::PUSH".to_string(),
-            Operations::ADD => "Operations::ADD".to_string(),
-            Operations::SUB => "Operations::SUB".to_string(),
-            Operations::DIV => "Operations::DIV".to_string(),
-            Operations::MULT => "Operations::MULT".to_string(),
-            Operations::HALT => "Operations::HALT".to_string(),
-            Operations::POP => "Operations::POP".to_string(),
-            Operations::VAR => "Operations::VAR".to_string(),
-            Operations::ARG(value) => format!("Operations::ARG({})", value),
-            Operations::FETCH => "Operations::FETCH".to_string(),
-            Operations::STORE => "Operations::STORE".to_string(),
-            Operations::JMP => "Operations::JMP".to_string(),
-            Operations::JZ => "Operations::JZ".to_string(),
-            Operations::JNZ => "Operations::JNZ".to_string(),
-            Operations::PRINT => "Operations::PRINT".to_string(),
-            Operations::LT => "Operations::LT".to_string(),
-            Operations::BT => "Operations::BT".to_string(),
-            Operations::EQ => "Operations::EQ".to_string(),
-            Operations::ARR => "Operations::ARR".to_string(),
-            Operations::SLICE => "Operations::SLICE".to_string(),
-            Operations::DROP => "Operations::DROP".to_string(),
-            Operations::INPUT => "Operations::INPUT".to_string(),
-            Operations::TYPE => "Operations::TYPE".to_string(),
-            Operations::LEN => "Operations::LEN".to_string(),
-            Operations::TO_INT => "Operations::TO_INT".to_string(),
-            Operations::TO_STR => "Operations::TO_STR".to_string(),
-            Operations::CLEAN => "Operations::CLEAN".to_string(),
-        };
-        write!(f, "{}", s)
-    }
-}
+const TRAILER_MAGIC: &[u8; 8] = b"HIWEXEC!";
+const TRAILER_SIZE: u64 = 16;
 
 pub struct Container {
     name: String,
-    vm: crate::vm::VM,
+    byte_code: ByteCode,
+    with_line_table: bool,
 }
 
 impl Container {
-    pub fn new(name: String, vm: crate::vm::VM) -> Self {
-        Container { name, vm }
+    pub fn new(name: String, byte_code: ByteCode, with_line_table: bool) -> Self {
+        Container {
+            name,
+            byte_code,
+            with_line_table,
+        }
     }
-    pub fn compile(self) {
-        let filenames = vec![format!("{}.rs", self.name), format!("{}.pdb", self.name)];
-        let operations_string_enum: String = self
-            .vm
-            .program
-            .iter()
-            .map(|item| item.to_string())
-            .collect::<Vec<String>>()
-            .join(",");
-
-        let vm_code = crate::filereader::get_vm_code();
-
-        let output_code = format!(
-            r#"{}
-fn main() {{
-    let program = vec![{}];
-    let mut vm = VM::new(program);
-
-    let _ = vm.run();
-}}
-"#,
-            vm_code, operations_string_enum
-        );
 
-        let mut output_file = std::fs::File::create(&filenames[0]).unwrap();
-        let _ = output_file.write_all(output_code.as_bytes());
+    pub fn compile(self) -> io::Result<()> {
+        let runtime_path = std::env::current_exe()?;
+        let mut runtime = std::fs::read(&runtime_path)?;
 
-        let compiler = std::process::Command::new("rustc")
-            .arg(&filenames[0])
-            .output()
-            .expect("Cannot compile VM");
+        // runtime that already carries a program gives only its own part
 
-        for fname in filenames {
-            let _ = std::fs::remove_file(fname);
+        if let Some((start, _)) = find_payload(&mut File::open(&runtime_path)?)? {
+            runtime.truncate(start as usize);
         }
 
-        match compiler.status.code() {
-            Some(0) => {
-                println!(
-                    "{} '{}' successfuly compiled!",
-                    "[BinaryCompiler]:".cyan(),
-                    &self.name
-                );
-            }
-            _ => {
-                eprintln!(
-                    "{} An error occured while compiling '{}'",
-                    "[BinaryCompiler]:".red(),
-                    &self.name
-                )
-            }
-        }
+        let payload = bytecode::encode(&self.byte_code, self.with_line_table);
+
+        runtime.extend_from_slice(&payload);
+        runtime.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        runtime.extend_from_slice(TRAILER_MAGIC);
+
+        let output = if cfg!(windows) && std::path::Path::new(&self.name).extension().is_none() {
+            format!("{}.exe", self.name)
+        } else {
+            self.name.clone()
+        };
+
+        std::fs::write(&output, runtime)?;
+
+        // output is executable same as runtime
+
+        let permissions = std::fs::metadata(&runtime_path)?.permissions();
+        std::fs::set_permissions(&output, permissions)?;
+
+        println!(
+            "{} '{}' successfuly compiled!",
+            "[BinaryCompiler]:".cyan(),
+            &output
+        );
+
+        Ok(())
     }
 }
+
+// position and length of the appended bytecode, if file has it
+
+fn find_payload(file: &mut File) -> io::Result<Option<(u64, u64)>> {
+    let size = file.metadata()?.len();
+
+    if size < TRAILER_SIZE {
+        return Ok(None);
+    }
+
+    let mut trailer = [0u8; TRAILER_SIZE as usize];
+
+    file.seek(SeekFrom::Start(size - TRAILER_SIZE))?;
+    file.read_exact(&mut trailer)?;
+
+    if &trailer[8..] != TRAILER_MAGIC {
+        return Ok(None);
+    }
+
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&trailer[..8]);
+    let length = u64::from_le_bytes(length);
+
+    if length > size - TRAILER_SIZE {
+        return Ok(None);
+    }
+
+    Ok(Some((size - TRAILER_SIZE - length, length)))
+}
+
+// bytecode appended to the running executable, `None` for plain hiw
+
+pub fn embedded() -> Option<Result<ByteCode, FormatError>> {
+    let mut file = File::open(std::env::current_exe().ok()?).ok()?;
+    let (start, length) = find_payload(&mut file).ok()??;
+
+    let mut payload = vec![0u8; length as usize];
+
+    file.seek(SeekFrom::Start(start)).ok()?;
+    file.read_exact(&mut payload).ok()?;
+
+    Some(bytecode::decode(&payload))
+}
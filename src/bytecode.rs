@@ -0,0 +1,1001 @@
+// hiw-lang compiler
+// https://github.com/mealet/hiw-lang
+// ----------------------------------------
+// Copyright ©️ 2024, mealet.
+// Project licensed under the BSD-3 License
+// that can be found in LICENSE file.
+// ----------------------------------------
+
+// Bytecode - binary `.hiwc` format for compiled programs, so they can run without lexing and
+// parsing again.
+//
+// Layout (all numbers are little-endian):
+//
+//     magic        4 bytes  "HIWC"
+//     version      u16
+//     flags        u8       bit 0 - line table is present
+//     constants    u32 count, then values
+//     functions    u32 count, then (name value, u32 arguments count, argument values, u32 address)
+//     code         u32 count, then instructions: u8 opcode and two u32 operands
+//     line table   only with flag: files, lines, function names and variable names
+//     checksum     u64      FNV-1a of everything before it
+
+use crate::{
+    compiler::ByteCode,
+    diagnostic::{Diagnostic, Phase, Span},
+    vm::{
+        change_operands, Closure, Function, GlobalName, LineEntry, LineTable, LocalNames,
+        Operations, SourceFile, Value, MAX_SLOTS,
+    },
+};
+use std::{collections::HashMap, fmt};
+
+pub const MAGIC: &[u8; 4] = b"HIWC";
+pub const FORMAT_VERSION: u16 = 4;
+pub const EXTENSION: &str = "hiwc";
+
+const FLAG_LINE_TABLE: u8 = 1;
+const INSTRUCTION_SIZE: usize = 9;
+
+const VALUE_INT: u8 = 0;
+const VALUE_FLOAT: u8 = 1;
+const VALUE_STR: u8 = 2;
+const VALUE_BOOL: u8 = 3;
+const VALUE_ARRAY: u8 = 4;
+const VALUE_MAP: u8 = 5;
+const VALUE_NULL: u8 = 6;
+const VALUE_FUNCTION: u8 = 7;
+
+// compiler never nests constants, the limit only keeps broken files from exhausting the stack
+
+const MAX_VALUE_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+    pub diagnostic: Box<Diagnostic>,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.diagnostic)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+fn error<T>(message: impl Into<String>) -> Result<T, FormatError> {
+    Err(FormatError {
+        diagnostic: Box::new(Diagnostic::error(Phase::Loader, message)),
+    })
+}
+
+// WARNING: Opcodes are part of the file format. Never renumber them, add new ones to the end and
+// increase FORMAT_VERSION when meaning of existing operation changes
+
+fn encode_operation(operation: &Operations) -> (u8, u32, u32) {
+    match *operation {
+        Operations::PUSH(index) => (0, index, 0),
+        Operations::ARR(count) => (1, count, 0),
+        Operations::SLICE => (2, 0, 0),
+        Operations::ADD => (3, 0, 0),
+        Operations::SUB => (4, 0, 0),
+        Operations::DIV => (5, 0, 0),
+        Operations::MULT => (6, 0, 0),
+        Operations::FETCH(name) => (8, name, 0),
+        Operations::STORE(name) => (9, name, 0),
+        Operations::CALL(address, argc) => (11, address, argc),
+        Operations::CALL_NATIVE(name, argc) => (12, name, argc),
+        Operations::RET => (13, 0, 0),
+        Operations::TYPE => (14, 0, 0),
+        Operations::LEN => (15, 0, 0),
+        Operations::TO_INT => (16, 0, 0),
+        Operations::TO_FLOAT => (17, 0, 0),
+        Operations::TO_STR => (18, 0, 0),
+        Operations::ROUND => (19, 0, 0),
+        Operations::FLOOR => (20, 0, 0),
+        Operations::PRINT => (21, 0, 0),
+        Operations::INPUT => (22, 0, 0),
+        Operations::LT => (23, 0, 0),
+        Operations::BT => (24, 0, 0),
+        Operations::EQ => (25, 0, 0),
+        Operations::JMP(offset) => (26, offset as u32, 0),
+        Operations::JZ(offset) => (27, offset as u32, 0),
+        Operations::JNZ(offset) => (28, offset as u32, 0),
+        Operations::DROP(name) => (29, name, 0),
+        Operations::POP => (30, 0, 0),
+        Operations::CLEAN => (31, 0, 0),
+        Operations::HALT => (32, 0, 0),
+        Operations::FETCH_LOCAL(slot) => (33, slot, 0),
+        Operations::STORE_LOCAL(slot) => (34, slot, 0),
+        Operations::DROP_LOCAL(slot) => (35, slot, 0),
+        Operations::NOT => (36, 0, 0),
+        Operations::NE => (37, 0, 0),
+        Operations::LE => (38, 0, 0),
+        Operations::BE => (39, 0, 0),
+        Operations::SLICE_SET => (40, 0, 0),
+        Operations::DUP(count) => (41, count, 0),
+        Operations::ARR_PUSH => (42, 0, 0),
+        Operations::ARR_POP => (43, 0, 0),
+        Operations::ARR_INSERT => (44, 0, 0),
+        Operations::REMOVE => (45, 0, 0),
+        Operations::JOIN => (46, 0, 0),
+        Operations::CONTAINS => (47, 0, 0),
+        Operations::MAP(count) => (48, count, 0),
+        Operations::KEYS => (49, 0, 0),
+        Operations::VALUES => (50, 0, 0),
+        Operations::ITER => (51, 0, 0),
+        Operations::CLOSURE(index, count) => (52, index, count),
+        Operations::CALL_INDIRECT(argc) => (53, argc, 0),
+        Operations::ARR_MAP => (54, 0, 0),
+        Operations::FILTER => (55, 0, 0),
+        Operations::REDUCE => (56, 0, 0),
+        Operations::SORT => (57, 0, 0),
+        Operations::REVERSE => (58, 0, 0),
+        Operations::ENUMERATE => (59, 0, 0),
+        Operations::ZIP => (60, 0, 0),
+        Operations::ANY => (61, 0, 0),
+        Operations::ALL => (62, 0, 0),
+        Operations::MIN => (63, 0, 0),
+        Operations::MAX => (64, 0, 0),
+        Operations::SUM => (65, 0, 0),
+        Operations::SLICE_RANGE => (66, 0, 0),
+        Operations::FETCH_ITEM(slot, depth) => (67, slot, depth),
+        Operations::FETCH_LOCAL_ITEM(slot, depth) => (68, slot, depth),
+        Operations::CALL_GLOBAL(slot, argc) => (69, slot, argc),
+        Operations::EDIT(slot, depth) => (70, slot, depth),
+        Operations::EDIT_LOCAL(slot, depth) => (71, slot, depth),
+    }
+}
+
+fn decode_operation(opcode: u8, a: u32, b: u32) -> Option<Operations> {
+    let operation = match opcode {
+        0 => Operations::PUSH(a),
+        1 => Operations::ARR(a),
+        2 => Operations::SLICE,
+        3 => Operations::ADD,
+        4 => Operations::SUB,
+        5 => Operations::DIV,
+        6 => Operations::MULT,
+        8 => Operations::FETCH(a),
+        9 => Operations::STORE(a),
+        11 => Operations::CALL(a, b),
+        12 => Operations::CALL_NATIVE(a, b),
+        13 => Operations::RET,
+        14 => Operations::TYPE,
+        15 => Operations::LEN,
+        16 => Operations::TO_INT,
+        17 => Operations::TO_FLOAT,
+        18 => Operations::TO_STR,
+        19 => Operations::ROUND,
+        20 => Operations::FLOOR,
+        21 => Operations::PRINT,
+        22 => Operations::INPUT,
+        23 => Operations::LT,
+        24 => Operations::BT,
+        25 => Operations::EQ,
+        26 => Operations::JMP(a as i32),
+        27 => Operations::JZ(a as i32),
+        28 => Operations::JNZ(a as i32),
+        29 => Operations::DROP(a),
+        30 => Operations::POP,
+        31 => Operations::CLEAN,
+        32 => Operations::HALT,
+        33 => Operations::FETCH_LOCAL(a),
+        34 => Operations::STORE_LOCAL(a),
+        35 => Operations::DROP_LOCAL(a),
+        36 => Operations::NOT,
+        37 => Operations::NE,
+        38 => Operations::LE,
+        39 => Operations::BE,
+        40 => Operations::SLICE_SET,
+        41 => Operations::DUP(a),
+        42 => Operations::ARR_PUSH,
+        43 => Operations::ARR_POP,
+        44 => Operations::ARR_INSERT,
+        45 => Operations::REMOVE,
+        46 => Operations::JOIN,
+        47 => Operations::CONTAINS,
+        48 => Operations::MAP(a),
+        49 => Operations::KEYS,
+        50 => Operations::VALUES,
+        51 => Operations::ITER,
+        52 => Operations::CLOSURE(a, b),
+        53 => Operations::CALL_INDIRECT(a),
+        54 => Operations::ARR_MAP,
+        55 => Operations::FILTER,
+        56 => Operations::REDUCE,
+        57 => Operations::SORT,
+        58 => Operations::REVERSE,
+        59 => Operations::ENUMERATE,
+        60 => Operations::ZIP,
+        61 => Operations::ANY,
+        62 => Operations::ALL,
+        63 => Operations::MIN,
+        64 => Operations::MAX,
+        65 => Operations::SUM,
+        66 => Operations::SLICE_RANGE,
+        67 => Operations::FETCH_ITEM(a, b),
+        68 => Operations::FETCH_LOCAL_ITEM(a, b),
+        69 => Operations::CALL_GLOBAL(a, b),
+        70 => Operations::EDIT(a, b),
+        71 => Operations::EDIT_LOCAL(a, b),
+        _ => return None,
+    };
+
+    Some(operation)
+}
+
+// operands must point into constant pool and program, VM trusts them
+
+fn check_operation(
+    operation: &Operations,
+    address: usize,
+    constants: &[Value],
+    program: &[Operations],
+) -> Result<(), FormatError> {
+    let (name, target) = match *operation {
+        Operations::EDIT(..) | Operations::EDIT_LOCAL(..)
+            if program
+                .get(address + 1)
+                .is_none_or(|next| change_operands(*next).is_none()) =>
+        {
+            return error(format!(
+                "Instruction {} is not followed by changing operation",
+                address
+            ))
+        }
+        Operations::CALL_NATIVE(name, _) => (Some(name), None),
+        Operations::FETCH(slot)
+        | Operations::STORE(slot)
+        | Operations::DROP(slot)
+        | Operations::FETCH_LOCAL(slot)
+        | Operations::STORE_LOCAL(slot)
+        | Operations::DROP_LOCAL(slot)
+        | Operations::FETCH_ITEM(slot, _)
+        | Operations::FETCH_LOCAL_ITEM(slot, _)
+        | Operations::CALL_GLOBAL(slot, _)
+        | Operations::EDIT(slot, _)
+        | Operations::EDIT_LOCAL(slot, _)
+            if slot as usize >= MAX_SLOTS =>
+        {
+            return error(format!("Variable slot {} is too big", slot))
+        }
+        Operations::CALL(address, _) => (None, Some(address as i64)),
+
+        // compiler never jumps to the same instruction, such jump only hangs the VM
+
+        Operations::JMP(0) => return error(format!("Instruction {} jumps to itself", address)),
+        Operations::JMP(offset) | Operations::JZ(offset) | Operations::JNZ(offset) => {
+            (None, Some(address as i64 + offset as i64))
+        }
+        Operations::PUSH(index) if index as usize >= constants.len() => {
+            return error(format!("Constant index {} is out of pool", index))
+        }
+        Operations::CLOSURE(index, _)
+            if !matches!(constants.get(index as usize), Some(Value::FUNCTION(_))) =>
+        {
+            return error(format!(
+                "Instruction {} has wrong function operand",
+                address
+            ))
+        }
+        _ => (None, None),
+    };
+
+    if let Some(name) = name {
+        if !matches!(constants.get(name as usize), Some(Value::STR(_))) {
+            return error(format!("Instruction {} has wrong name operand", address));
+        }
+    }
+
+    if let Some(target) = target {
+        if target < 0 || target >= program.len() as i64 {
+            return error(format!("Instruction {} points out of code", address));
+        }
+    }
+
+    Ok(())
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+// Writing
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::INT(int) => {
+                self.u8(VALUE_INT);
+                self.bytes.extend_from_slice(&int.to_le_bytes());
+            }
+            Value::FLOAT(float) => {
+                self.u8(VALUE_FLOAT);
+                self.bytes.extend_from_slice(&float.to_bits().to_le_bytes());
+            }
+            Value::STR(string) => {
+                self.u8(VALUE_STR);
+                self.string(string);
+            }
+            Value::BOOL(bool) => {
+                self.u8(VALUE_BOOL);
+                self.u8(*bool as u8);
+            }
+            Value::ARRAY(array) => {
+                self.u8(VALUE_ARRAY);
+                self.u32(array.len());
+
+                for item in array {
+                    self.value(item);
+                }
+            }
+            Value::NULL => self.u8(VALUE_NULL),
+            Value::FUNCTION(function) => {
+                self.u8(VALUE_FUNCTION);
+                self.u32(function.address);
+                self.u32(function.arity);
+                self.u32(function.captures.len());
+
+                for capture in &function.captures {
+                    self.value(capture);
+                }
+            }
+            Value::MAP(map) => {
+                self.u8(VALUE_MAP);
+                self.u32(map.len());
+
+                for (key, value) in map {
+                    self.value(key);
+                    self.value(value);
+                }
+            }
+        }
+    }
+}
+
+pub fn encode(byte_code: &ByteCode, with_line_table: bool) -> Vec<u8> {
+    let mut writer = Writer { bytes: Vec::new() };
+
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u16(FORMAT_VERSION);
+    writer.u8(if with_line_table { FLAG_LINE_TABLE } else { 0 });
+
+    writer.u32(byte_code.constants.len());
+    for constant in &byte_code.constants {
+        writer.value(constant);
+    }
+
+    // functions are sorted, so the same program always gives the same file
+
+    let mut functions = byte_code.functions.iter().collect::<Vec<_>>();
+    functions.sort_by_key(|(name, _)| name.to_string());
+
+    writer.u32(functions.len());
+    for (_, function) in functions {
+        writer.value(&function.name);
+        writer.u32(function.arguments.len());
+
+        for argument in &function.arguments {
+            writer.value(argument);
+        }
+
+        writer.u32(function.address);
+    }
+
+    writer.u32(byte_code.program.len());
+    for operation in &byte_code.program {
+        let (opcode, a, b) = encode_operation(operation);
+
+        writer.u8(opcode);
+        writer.u32(a as usize);
+        writer.u32(b as usize);
+    }
+
+    if with_line_table {
+        let line_table = &byte_code.line_table;
+
+        writer.u32(line_table.files.len());
+        for file in &line_table.files {
+            writer.string(&file.name);
+            writer.string(&file.source);
+        }
+
+        writer.u32(line_table.lines.len());
+        for entry in &line_table.lines {
+            writer.u32(entry.file);
+            writer.u32(entry.span.line);
+            writer.u32(entry.span.start);
+            writer.u32(entry.span.end);
+        }
+
+        writer.u32(line_table.functions.len());
+        for (address, name) in &line_table.functions {
+            writer.u32(*address);
+            writer.string(name);
+        }
+
+        writer.u32(line_table.globals.len());
+        for global in &line_table.globals {
+            writer.string(&global.name);
+            writer.u8(global.scoped as u8);
+        }
+
+        writer.u32(line_table.locals.len());
+        for locals in &line_table.locals {
+            writer.u32(locals.function);
+            writer.u32(locals.end);
+            writer.u32(locals.names.len());
+
+            for name in &locals.names {
+                writer.string(name);
+            }
+        }
+    }
+
+    let hash = checksum(&writer.bytes);
+    writer.bytes.extend_from_slice(&hash.to_le_bytes());
+
+    writer.bytes
+}
+
+// Reading
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() - self.position < count {
+            return error("File is truncated");
+        }
+
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, FormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    // count of items which take at least `item_size` bytes each, so corrupted counts cannot
+    // allocate more memory than file has
+
+    fn count(&mut self, item_size: usize) -> Result<usize, FormatError> {
+        let count = self.u32()?;
+
+        if count.saturating_mul(item_size) > self.bytes.len() - self.position {
+            return error("Item count is bigger than file");
+        }
+
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let length = self.count(1)?;
+        let bytes = self.take(length)?;
+
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(string) => Ok(string),
+            Err(_) => error("String is not valid UTF-8"),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, FormatError> {
+        if depth > MAX_VALUE_DEPTH {
+            return error("Constant is nested too deep");
+        }
+
+        let value = match self.u8()? {
+            VALUE_INT => {
+                let bytes = self.take(4)?;
+                Value::INT(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            VALUE_FLOAT => {
+                let bytes = self.take(8)?;
+                let mut bits = [0u8; 8];
+                bits.copy_from_slice(bytes);
+
+                Value::FLOAT(f64::from_bits(u64::from_le_bytes(bits)))
+            }
+            VALUE_STR => Value::STR(self.string()?),
+            VALUE_BOOL => match self.u8()? {
+                0 => Value::BOOL(false),
+                1 => Value::BOOL(true),
+                other => return error(format!("Wrong BOOL value {}", other)),
+            },
+            VALUE_ARRAY => {
+                let count = self.count(1)?;
+                let mut array = Vec::with_capacity(count);
+
+                for _ in 0..count {
+                    array.push(self.value(depth + 1)?);
+                }
+
+                Value::ARRAY(array)
+            }
+            VALUE_NULL => Value::NULL,
+            VALUE_FUNCTION => {
+                let address = self.u32()?;
+                let arity = self.u32()?;
+                let count = self.count(1)?;
+                let mut captures = Vec::with_capacity(count);
+
+                for _ in 0..count {
+                    captures.push(self.value(depth + 1)?);
+                }
+
+                Value::FUNCTION(Closure {
+                    address,
+                    arity,
+                    captures,
+                })
+            }
+            VALUE_MAP => {
+                let count = self.count(2)?;
+                let mut map = Vec::with_capacity(count);
+
+                for _ in 0..count {
+                    map.push((self.value(depth + 1)?, self.value(depth + 1)?));
+                }
+
+                Value::MAP(map)
+            }
+            tag => return error(format!("Unknown value type {}", tag)),
+        };
+
+        Ok(value)
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<ByteCode, FormatError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return error("File is not a hiw bytecode");
+    }
+
+    if bytes.len() < MAGIC.len() + 2 + 8 {
+        return error("File is truncated");
+    }
+
+    let (content, hash) = bytes.split_at(bytes.len() - 8);
+
+    let mut reader = Reader {
+        bytes: content,
+        position: MAGIC.len(),
+    };
+
+    // version goes before checksum check, so files of other versions get clear message
+
+    let version = reader.u16()?;
+
+    if version != FORMAT_VERSION {
+        return error(format!(
+            "File has bytecode version {}, but this hiw supports only version {}",
+            version, FORMAT_VERSION
+        ));
+    }
+
+    let mut expected_hash = [0u8; 8];
+    expected_hash.copy_from_slice(hash);
+
+    if checksum(content) != u64::from_le_bytes(expected_hash) {
+        return error("File is corrupted (checksum mismatch)");
+    }
+
+    let flags = reader.u8()?;
+
+    let constants_count = reader.count(2)?;
+    let mut constants = Vec::with_capacity(constants_count);
+
+    for _ in 0..constants_count {
+        constants.push(reader.value(0)?);
+    }
+
+    let functions_count = reader.count(9)?;
+    let mut functions = HashMap::new();
+
+    for _ in 0..functions_count {
+        let name = reader.value(0)?;
+        let arguments_count = reader.count(2)?;
+        let mut arguments = Vec::with_capacity(arguments_count);
+
+        for _ in 0..arguments_count {
+            arguments.push(reader.value(0)?);
+        }
+
+        let address = reader.u32()?;
+
+        let key = match &name {
+            Value::STR(name) => name.clone(),
+            _ => return error("Function name must be STR"),
+        };
+
+        functions.insert(
+            key,
+            Function {
+                name,
+                arguments,
+                address,
+            },
+        );
+    }
+
+    let code_count = reader.count(INSTRUCTION_SIZE)?;
+    let mut program = Vec::with_capacity(code_count);
+
+    for _ in 0..code_count {
+        let opcode = reader.u8()?;
+        let a = reader.u32()? as u32;
+        let b = reader.u32()? as u32;
+
+        match decode_operation(opcode, a, b) {
+            Some(operation) => program.push(operation),
+            None => return error(format!("Unknown opcode {}", opcode)),
+        }
+    }
+
+    for (address, operation) in program.iter().enumerate() {
+        check_operation(operation, address, &constants, &program)?;
+    }
+
+    let mut line_table = LineTable::default();
+
+    if flags & FLAG_LINE_TABLE != 0 {
+        let files_count = reader.count(8)?;
+
+        for _ in 0..files_count {
+            let name = reader.string()?;
+            let source = reader.string()?;
+
+            line_table.files.push(SourceFile { name, source });
+        }
+
+        let lines_count = reader.count(16)?;
+
+        for _ in 0..lines_count {
+            let file = reader.u32()?;
+            let line = reader.u32()?;
+            let start = reader.u32()?;
+            let end = reader.u32()?;
+
+            if file >= line_table.files.len() {
+                return error("Line table points to unknown file");
+            }
+
+            line_table.lines.push(LineEntry {
+                file,
+                span: Span { line, start, end },
+            });
+        }
+
+        let names_count = reader.count(8)?;
+
+        for _ in 0..names_count {
+            let address = reader.u32()?;
+            let name = reader.string()?;
+
+            line_table.functions.push((address, name));
+        }
+
+        let globals_count = reader.count(5)?;
+
+        for _ in 0..globals_count {
+            let name = reader.string()?;
+            let scoped = reader.u8()? != 0;
+
+            line_table.globals.push(GlobalName { name, scoped });
+        }
+
+        let locals_count = reader.count(12)?;
+
+        for _ in 0..locals_count {
+            let function = reader.u32()?;
+            let end = reader.u32()?;
+            let names_count = reader.count(4)?;
+            let mut names = Vec::with_capacity(names_count);
+
+            for _ in 0..names_count {
+                names.push(reader.string()?);
+            }
+
+            line_table.locals.push(LocalNames {
+                function,
+                end,
+                names,
+            });
+        }
+
+        if line_table.lines.len() != program.len() {
+            return error("Line table doesn't match code");
+        }
+    }
+
+    if reader.position != content.len() {
+        return error("File has unexpected data after code");
+    }
+
+    // program must end with HALT and functions must point into it
+
+    if program.last() != Some(&Operations::HALT) {
+        return error("Code doesn't end with HALT");
+    }
+
+    for function in functions.values() {
+        if function.address >= program.len() {
+            return error(format!("Function {:?} points out of code", function.name));
+        }
+    }
+
+    for constant in &constants {
+        if let Value::FUNCTION(function) = constant {
+            if function.address >= program.len() {
+                return error("Function value points out of code");
+            }
+        }
+    }
+
+    Ok(ByteCode {
+        program,
+        constants,
+        functions,
+        jump_codes: Vec::new(),
+        line_table,
+        warnings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "define add(a, b) { return a + b; };
+numbers = [1, 2.5, \"three\", true, null];
+points = {\"x\": 1, \"y\": [2, 3]};
+offset = 10;
+shift = define(n) { return n + offset; };
+numbers[0] = add(shift(1), points[\"y\"][1]);
+print(numbers);";
+
+    fn byte_code() -> ByteCode {
+        crate::compile("test.hiw", SOURCE).unwrap()
+    }
+
+    fn decode_error(bytes: &[u8]) -> String {
+        decode(bytes).unwrap_err().diagnostic.message
+    }
+
+    // file of changed program, checksum is valid so the program itself is checked
+
+    fn with_program(change: impl FnOnce(&mut Vec<Operations>)) -> Vec<u8> {
+        let mut byte_code = byte_code();
+        change(&mut byte_code.program);
+
+        encode(&byte_code, false)
+    }
+
+    fn with_checksum(mut content: Vec<u8>) -> Vec<u8> {
+        let hash = checksum(&content);
+        content.extend_from_slice(&hash.to_le_bytes());
+
+        content
+    }
+
+    #[test]
+    fn round_trip_keeps_program() {
+        let byte_code = byte_code();
+
+        for with_line_table in [true, false] {
+            let bytes = encode(&byte_code, with_line_table);
+            let decoded = decode(&bytes).unwrap();
+
+            assert_eq!(decoded.program, byte_code.program);
+            assert_eq!(decoded.constants, byte_code.constants);
+            assert_eq!(decoded.functions, byte_code.functions);
+            assert_eq!(encode(&decoded, with_line_table), bytes);
+        }
+
+        let decoded = decode(&encode(&byte_code, true)).unwrap();
+        assert_eq!(decoded.line_table, byte_code.line_table);
+
+        let stripped = decode(&encode(&byte_code, false)).unwrap();
+        assert_eq!(stripped.line_table, LineTable::default());
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = encode(&byte_code(), true);
+
+        for length in 0..bytes.len() {
+            assert!(decode(&bytes[..length]).is_err(), "length {}", length);
+        }
+
+        // with valid checksum truncated content is found by reader
+
+        let content = bytes[..bytes.len() - 8].to_vec();
+        let truncated = with_checksum(content[..content.len() / 2].to_vec());
+
+        assert!(decode(&truncated).is_err());
+        assert_eq!(decode_error(&bytes[..MAGIC.len() + 1]), "File is truncated");
+    }
+
+    #[test]
+    fn corrupted_file_is_rejected() {
+        let mut bytes = encode(&byte_code(), true);
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+
+        assert_eq!(
+            decode_error(&bytes),
+            "File is corrupted (checksum mismatch)"
+        );
+        assert_eq!(decode_error(b"HIWX\x04\x00"), "File is not a hiw bytecode");
+
+        let mut bytes = encode(&byte_code(), true);
+        bytes.push(0);
+
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let mut bytes = encode(&byte_code(), true);
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert_eq!(
+            decode_error(&bytes),
+            format!(
+                "File has bytecode version {}, but this hiw supports only version {}",
+                FORMAT_VERSION + 1,
+                FORMAT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn out_of_range_operands_are_rejected() {
+        let cases: [(Operations, &str); 7] = [
+            (Operations::PUSH(1000), "Constant index 1000 is out of pool"),
+            (Operations::JMP(1000), "Instruction 0 points out of code"),
+            (Operations::JZ(-1), "Instruction 0 points out of code"),
+            (
+                Operations::CALL(1000, 0),
+                "Instruction 0 points out of code",
+            ),
+            (
+                Operations::FETCH(u32::MAX),
+                "Variable slot 4294967295 is too big",
+            ),
+            (
+                Operations::CALL_NATIVE(1000, 0),
+                "Instruction 0 has wrong name operand",
+            ),
+            (
+                Operations::CLOSURE(1000, 0),
+                "Instruction 0 has wrong function operand",
+            ),
+        ];
+
+        for (operation, message) in cases {
+            let bytes = with_program(|program| program[0] = operation);
+            assert_eq!(decode_error(&bytes), message);
+        }
+
+        let bytes = with_program(|program| program[0] = Operations::EDIT(0, 0));
+        assert_eq!(
+            decode_error(&bytes),
+            "Instruction 0 is not followed by changing operation"
+        );
+
+        let bytes = with_program(|program| {
+            program.pop();
+        });
+        assert_eq!(decode_error(&bytes), "Code doesn't end with HALT");
+    }
+
+    #[test]
+    fn jump_to_itself_is_rejected() {
+        let bytes = with_program(|program| program[0] = Operations::JMP(0));
+        assert_eq!(decode_error(&bytes), "Instruction 0 jumps to itself");
+    }
+
+    #[test]
+    fn unknown_opcode_and_value_are_rejected() {
+        let mut content = encode(&byte_code(), false);
+        content.truncate(content.len() - 8);
+
+        // first instruction goes after magic, version, flags, constants and functions
+
+        let mut reader = Reader {
+            bytes: &content,
+            position: MAGIC.len() + 3,
+        };
+
+        for _ in 0..reader.u32().unwrap() {
+            reader.value(0).unwrap();
+        }
+
+        for _ in 0..reader.u32().unwrap() {
+            reader.value(0).unwrap();
+
+            for _ in 0..reader.u32().unwrap() {
+                reader.value(0).unwrap();
+            }
+
+            reader.u32().unwrap();
+        }
+
+        let code = reader.position + 4;
+
+        let mut changed = content.clone();
+        changed[code] = 255;
+        assert_eq!(decode_error(&with_checksum(changed)), "Unknown opcode 255");
+
+        // first constant type
+
+        let mut changed = content.clone();
+        changed[MAGIC.len() + 7] = 255;
+        assert_eq!(
+            decode_error(&with_checksum(changed)),
+            "Unknown value type 255"
+        );
+    }
+
+    #[test]
+    fn deeply_nested_constant_is_rejected() {
+        let mut value = Value::INT(1);
+
+        for _ in 0..=MAX_VALUE_DEPTH {
+            value = Value::ARRAY(vec![value]);
+        }
+
+        let mut byte_code = byte_code();
+        byte_code.constants.push(value);
+
+        assert_eq!(
+            decode_error(&encode(&byte_code, false)),
+            "Constant is nested too deep"
+        );
+    }
+}
@@ -9,47 +9,162 @@
 // Compiler - magic wand which converts AST to virtual machine code.
 
 use crate::{
-    parser::{Kind, Node},
-    vm::Operations,
-    vm::Value,
+    diagnostic::{Diagnostic, Location, Phase, Span},
+    lexer::LexError,
+    parser::{Kind, Node, ParseError},
+    vm::{
+        Closure, GlobalName, LineEntry, LineTable, LocalNames, Operations, SourceFile, Value,
+        MAX_SLOTS, VM,
+    },
 };
-use colored::Colorize;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 #[derive(Debug, Clone)]
 pub struct Compiler {
     program: Vec<Operations>,
+    constants: Vec<Value>,
+    constant_indexes: HashMap<String, u32>,
     functions: HashMap<String, crate::vm::Function>,
+    calls: Vec<FunctionCall>,
+    natives: Vec<FunctionCall>,
     jump_codes: Vec<usize>,
+    globals: HashMap<String, Global>,
+    forward_reads: Vec<(String, Location)>,
+    forward_values: Vec<ForwardValue>,
+    blocks: Vec<Block>,
+    scopes: Vec<FunctionScope>,
+    loops: Vec<LoopContext>,
+    line_table: LineTable,
+    file: usize,
+    span: Span,
+    pub warnings: Vec<Diagnostic>,
     pub pc: i32,
 }
 
+// Variable slot - place of the variable value, names are resolved to slots while compiling
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    GLOBAL(u32),
+    LOCAL(u32),
+}
+
+// Global variable, functions can read it before the declaration at top level
+
+#[derive(Debug, Clone, Copy)]
+struct Global {
+    slot: u32,
+    declared: bool,
+}
+
+// Block - names declared in function, `if`, `while` or `for` body, visible until the block end
+
+#[derive(Debug, Clone, Default)]
+struct Block {
+    names: HashMap<String, Slot>,
+}
+
+// Function which body is compiling now, its locals are numbered in order of appearance and
+// its blocks start from `blocks` index
+
+#[derive(Debug, Clone)]
+struct FunctionScope {
+    address: usize,
+    names: Vec<String>,
+    blocks: usize,
+}
+
+// Loop which body is compiling now, its `break` and `continue` jumps are patched after the body
+
+#[derive(Debug, Clone, Default)]
+struct LoopContext {
+    breaks: Vec<i32>,
+    continues: Vec<i32>,
+}
+
+// Call site which address will be resolved after compiling all functions, only calls in
+// functions can use global declared after them
+
+#[derive(Debug, Clone)]
+struct FunctionCall {
+    address: usize,
+    name: String,
+    arguments: usize,
+    location: Location,
+    in_function: bool,
+}
+
+// Name read before its declaration: global read by function (`slot` is fetched) or function
+// defined later, instruction at `address` is replaced with function value while linking
+
+#[derive(Debug, Clone)]
+struct ForwardValue {
+    address: usize,
+    name: String,
+    slot: Option<u32>,
+    location: Location,
+}
+
+// Operations available in `op!` macro, operands are written after the name: op!(FETCH a TYPE)
+
 lazy_static! {
     pub static ref OPERATIONS_MAP: HashMap<&'static str, Operations> = {
         let mut m = HashMap::new();
-        m.insert("PUSH", Operations::PUSH);
-        m.insert("ARR", Operations::ARR);
+        m.insert("PUSH", Operations::PUSH(0));
+        m.insert("ARR", Operations::ARR(0));
         m.insert("SLICE", Operations::SLICE);
         m.insert("ADD", Operations::ADD);
         m.insert("SUB", Operations::SUB);
         m.insert("DIV", Operations::DIV);
         m.insert("MULT", Operations::MULT);
-        m.insert("VAR", Operations::VAR);
-        m.insert("FETCH", Operations::FETCH);
-        m.insert("STORE", Operations::STORE);
+        m.insert("FETCH", Operations::FETCH(0));
+        m.insert("STORE", Operations::STORE(0));
+        m.insert("LOCAL", Operations::STORE_LOCAL(0));
         m.insert("TYPE", Operations::TYPE);
         m.insert("TO_INT", Operations::TO_INT);
+        m.insert("TO_FLOAT", Operations::TO_FLOAT);
+        m.insert("ROUND", Operations::ROUND);
+        m.insert("FLOOR", Operations::FLOOR);
         m.insert("TO_STR", Operations::TO_STR);
         m.insert("LEN", Operations::LEN);
+        m.insert("SLICE_SET", Operations::SLICE_SET);
+        m.insert("ARR_PUSH", Operations::ARR_PUSH);
+        m.insert("ARR_POP", Operations::ARR_POP);
+        m.insert("ARR_INSERT", Operations::ARR_INSERT);
+        m.insert("REMOVE", Operations::REMOVE);
+        m.insert("JOIN", Operations::JOIN);
+        m.insert("CONTAINS", Operations::CONTAINS);
+        m.insert("KEYS", Operations::KEYS);
+        m.insert("VALUES", Operations::VALUES);
+        m.insert("ITER", Operations::ITER);
+        m.insert("ARR_MAP", Operations::ARR_MAP);
+        m.insert("FILTER", Operations::FILTER);
+        m.insert("REDUCE", Operations::REDUCE);
+        m.insert("SORT", Operations::SORT);
+        m.insert("REVERSE", Operations::REVERSE);
+        m.insert("ENUMERATE", Operations::ENUMERATE);
+        m.insert("ZIP", Operations::ZIP);
+        m.insert("ANY", Operations::ANY);
+        m.insert("ALL", Operations::ALL);
+        m.insert("MIN", Operations::MIN);
+        m.insert("MAX", Operations::MAX);
+        m.insert("SUM", Operations::SUM);
+        m.insert("SLICE_RANGE", Operations::SLICE_RANGE);
         m.insert("PRINT", Operations::PRINT);
         m.insert("INPUT", Operations::INPUT);
         m.insert("LT", Operations::LT);
         m.insert("BT", Operations::BT);
         m.insert("EQ", Operations::EQ);
-        m.insert("JMP", Operations::JMP);
-        m.insert("JZ", Operations::JZ);
-        m.insert("JNZ", Operations::JNZ);
-        m.insert("DROP", Operations::DROP);
+        m.insert("NE", Operations::NE);
+        m.insert("LE", Operations::LE);
+        m.insert("BE", Operations::BE);
+        m.insert("NOT", Operations::NOT);
+        m.insert("JMP", Operations::JMP(0));
+        m.insert("JZ", Operations::JZ(0));
+        m.insert("JNZ", Operations::JNZ(0));
+        m.insert("DROP", Operations::DROP(0));
+        m.insert("DUP", Operations::DUP(0));
+        m.insert("CALL_INDIRECT", Operations::CALL_INDIRECT(0));
         m.insert("POP", Operations::POP);
         m.insert("CLEAN", Operations::CLEAN);
         m.insert("HALT", Operations::HALT);
@@ -57,113 +172,1047 @@ lazy_static! {
     };
 }
 
-// WARNING: Compare struct with binary compiler
+// Array methods changing their array: operation and count of arguments
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+lazy_static! {
+    static ref ARRAY_METHODS: HashMap<&'static str, (Operations, usize)> = HashMap::from([
+        ("push", (Operations::ARR_PUSH, 1)),
+        ("pop", (Operations::ARR_POP, 0)),
+        ("insert", (Operations::ARR_INSERT, 2)),
+        ("remove", (Operations::REMOVE, 1)),
+    ]);
+}
+
+// Chunk - compiled REPL input: where it starts and if it leaves expression result on the stack
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chunk {
+    pub start: usize,
+    pub has_result: bool,
+}
+
+// Compile error - semantic errors and errors of imported modules
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<Diagnostic> for CompileError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        CompileError {
+            diagnostics: vec![diagnostic],
+        }
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(error: LexError) -> Self {
+        CompileError {
+            diagnostics: error.diagnostics,
+        }
+    }
+}
+
+impl From<ParseError> for CompileError {
+    fn from(error: ParseError) -> Self {
+        CompileError {
+            diagnostics: error.diagnostics,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rendered = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", rendered.join("\n"))
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// WARNING: Compare struct with bytecode format
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ByteCode {
     pub program: Vec<Operations>,
+    pub constants: Vec<Value>,
     pub functions: HashMap<String, crate::vm::Function>,
     pub jump_codes: Vec<usize>,
+    pub line_table: LineTable,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl From<ByteCode> for VM {
+    fn from(byte_code: ByteCode) -> Self {
+        let mut vm = VM::new(byte_code.program);
+
+        vm.constants = byte_code.constants;
+        vm.functions = byte_code.functions;
+        vm.line_table = byte_code.line_table;
+        vm
+    }
 }
 
 impl Compiler {
-    pub fn new() -> Self {
+    pub fn new(filename: String, source: String) -> Self {
         Compiler {
             program: Vec::new(),
+            constants: Vec::new(),
+            constant_indexes: HashMap::new(),
             functions: HashMap::new(),
+            calls: Vec::new(),
+            natives: Vec::new(),
             jump_codes: Vec::new(),
+            globals: HashMap::new(),
+            forward_reads: Vec::new(),
+            forward_values: Vec::new(),
+            blocks: Vec::new(),
+            scopes: Vec::new(),
+            loops: Vec::new(),
+            line_table: LineTable {
+                files: vec![SourceFile {
+                    name: filename,
+                    source,
+                }],
+                ..Default::default()
+            },
+            file: 0,
+            span: Span::default(),
+            warnings: Vec::new(),
             pc: 0,
         }
     }
 
-    pub fn error(&self, message: &str) {
-        eprintln!("{} {}", "[CompilerError]:".red(), message);
-        std::process::exit(1);
+    fn location(&self, span: Span) -> Location {
+        let file = &self.line_table.files[self.file];
+        Location::new(&file.name, &file.source, span)
+    }
+
+    pub fn error(&self, span: Span, message: &str) -> CompileError {
+        CompileError::from(Diagnostic::error(Phase::Compiler, message).located(self.location(span)))
+    }
+
+    fn warning(&mut self, span: Span, message: &str, note: &str) {
+        let warning = Diagnostic::warning(Phase::Compiler, message)
+            .located(self.location(span))
+            .with_note(note);
+
+        self.warnings.push(warning);
     }
 
     fn gen(&mut self, command: Operations) {
         self.program.push(command);
-        self.pc = self.pc + 1;
+        self.line_table.lines.push(LineEntry {
+            file: self.file,
+            span: self.span,
+        });
+        self.pc += 1;
     }
 
-    pub fn compile_all(&mut self, nodes: Vec<Node>) -> ByteCode {
-        for n in nodes {
-            self.compile(n);
+    // index of value in the constant pool, same values are stored once
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let key = format!("{:?}", value);
+
+        if let Some(index) = self.constant_indexes.get(&key) {
+            return *index;
         }
 
-        self.gen(Operations::HALT);
+        self.constants.push(value);
+        self.constant_indexes
+            .insert(key, (self.constants.len() - 1) as u32);
+
+        (self.constants.len() - 1) as u32
+    }
+
+    // variables resolving: name is looked up from the innermost block of current function to its
+    // body, then in globals declared at top level. Assigning to unknown name declares it in the
+    // innermost block, so inner blocks see outer variables and the loop variable of `for` shadows
+    // the outer one
+
+    fn new_global(&mut self, name: &str, scoped: bool) -> Result<u32, CompileError> {
+        if self.line_table.globals.len() >= MAX_SLOTS {
+            return Err(self.error(self.span, "Too many global variables!"));
+        }
+
+        self.line_table.globals.push(GlobalName {
+            name: name.to_string(),
+            scoped,
+        });
+
+        Ok((self.line_table.globals.len() - 1) as u32)
+    }
+
+    fn global_slot(&mut self, name: &str) -> Result<u32, CompileError> {
+        if let Some(global) = self.globals.get(name) {
+            return Ok(global.slot);
+        }
+
+        let slot = self.new_global(name, false)?;
+
+        self.globals.insert(
+            name.to_string(),
+            Global {
+                slot,
+                declared: false,
+            },
+        );
+
+        Ok(slot)
+    }
+
+    // name declared in blocks of current function (or in blocks at top level)
+
+    fn block_slot(&self, name: &str) -> Option<Slot> {
+        let first_block = self.scopes.last().map_or(0, |scope| scope.blocks);
+
+        self.blocks[first_block..]
+            .iter()
+            .rev()
+            .find_map(|block| block.names.get(name).copied())
+    }
+
+    fn lookup(&self, name: &str) -> Option<Slot> {
+        if let Some(slot) = self.block_slot(name) {
+            return Some(slot);
+        }
+
+        match self.globals.get(name) {
+            Some(global) if global.declared => Some(Slot::GLOBAL(global.slot)),
+            _ => None,
+        }
+    }
+
+    fn declare(&mut self, name: &str) -> Result<Slot, CompileError> {
+        if let Some(slot) = self.blocks.last().and_then(|block| block.names.get(name)) {
+            return Ok(*slot);
+        }
+
+        let slot = match self.scopes.last_mut() {
+            Some(scope) if scope.names.len() >= MAX_SLOTS => {
+                return Err(self.error(self.span, "Too many local variables!"))
+            }
+            Some(scope) => {
+                scope.names.push(name.to_string());
+                Slot::LOCAL((scope.names.len() - 1) as u32)
+            }
+
+            // block at top level keeps its variables in hidden global slots
+
+            None if !self.blocks.is_empty() => Slot::GLOBAL(self.new_global(name, true)?),
+            // global and function can't share the name, else reading and calling it would
+            // give different values
+
+            None if self.functions.contains_key(name) => {
+                return Err(self.error(
+                    self.span,
+                    format!("Name '{}' is already used by function!", name).as_str(),
+                ))
+            }
+            None => {
+                let slot = self.global_slot(name)?;
+
+                if let Some(global) = self.globals.get_mut(name) {
+                    global.declared = true;
+                }
+
+                return Ok(Slot::GLOBAL(slot));
+            }
+        };
+
+        if let Some(block) = self.blocks.last_mut() {
+            block.names.insert(name.to_string(), slot);
+        }
+
+        Ok(slot)
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Slot, CompileError> {
+        if let Some(slot) = self.lookup(name) {
+            return Ok(slot);
+        }
+
+        if self.scopes.is_empty() {
+            return Err(self.error(
+                self.span,
+                format!("Variable '{}' is not declared!", name).as_str(),
+            ));
+        }
+
+        // function can read global declared after it, that is checked while linking
+
+        let location = self.location(self.span);
+        self.forward_reads.push((name.to_string(), location));
+
+        Ok(Slot::GLOBAL(self.global_slot(name)?))
+    }
+
+    fn resolve_assignment(&mut self, name: &str) -> Result<Slot, CompileError> {
+        match self.lookup(name) {
+            Some(slot) => Ok(slot),
+            None => self.declare(name),
+        }
+    }
+
+    // Assignment target `a[i][j]`: variable name and indexes from the outer one
 
-        return ByteCode {
+    fn assignment_target(&self, node: Node) -> Result<(String, Vec<Node>), CompileError> {
+        let mut indexes = Vec::new();
+        let mut node = node;
+
+        while node.kind == Kind::SLICE {
+            indexes.push(*node.op2.unwrap());
+            node = *node.op1.unwrap();
+        }
+
+        if node.kind != Kind::VAR {
+            return Err(self.error(node.span, "Only variables and their items can be changed!"));
+        }
+
+        indexes.reverse();
+        Ok((self.variable_name(&node)?, indexes))
+    }
+
+    // changing item of variable in place: indexes of the item and operands of `operation` are
+    // on the stack, EDIT finds the item and `operation` changes it without copying the variable
+
+    fn edit(&mut self, slot: Slot, depth: usize, operation: Operations) {
+        self.gen(match slot {
+            Slot::GLOBAL(slot) => Operations::EDIT(slot, depth as u32),
+            Slot::LOCAL(slot) => Operations::EDIT_LOCAL(slot, depth as u32),
+        });
+        self.gen(operation);
+    }
+
+    fn array_method(node: &Node) -> Option<(&'static str, Operations, usize)> {
+        match &node.value {
+            Some(Value::STR(name)) => ARRAY_METHODS
+                .get_key_value(name.as_str())
+                .map(|(method, (operation, arity))| (*method, *operation, *arity)),
+            _ => None,
+        }
+    }
+
+    // `a.push(x)`, `a.pop()`, `a.insert(i, x)` and `a.remove(i)` change array in its variable,
+    // result of the method stays on the stack
+
+    fn compile_array_method(
+        &mut self,
+        node: Node,
+        method: &str,
+        operation: Operations,
+        arity: usize,
+    ) -> Result<(), CompileError> {
+        let mut arguments = Vec::new();
+        Compiler::flatten_arguments(*node.op2.unwrap(), &mut arguments);
+
+        if arguments.len() != arity {
+            return Err(self.error(
+                node.span,
+                format!("'{}' takes {} argument(s), but {} given!", method, arity, arguments.len())
+                    .as_str(),
+            ));
+        }
+
+        let (name, indexes) = self.assignment_target(*node.op1.unwrap())?;
+        let slot = self.resolve(&name)?;
+        let depth = indexes.len();
+
+        for index in indexes {
+            self.compile(index)?;
+        }
+
+        for argument in arguments {
+            self.compile(argument)?;
+        }
+
+        self.edit(slot, depth, operation);
+
+        Ok(())
+    }
+
+    fn function_arguments(&self, name: &str, node: Node) -> Result<Vec<Value>, CompileError> {
+        let mut arguments_nodes = Vec::new();
+        Compiler::flatten_arguments(node, &mut arguments_nodes);
+
+        let mut formatted_args: Vec<Value> = Vec::new();
+
+        for arg in arguments_nodes {
+            match (arg.kind, arg.value.clone()) {
+                (Kind::VAR, Some(val)) => formatted_args.push(val),
+                _ => {
+                    return Err(self.error(
+                        arg.span,
+                        format!("Arguments of '{}' function must be names!", name).as_str(),
+                    ))
+                }
+            }
+        }
+
+        Ok(formatted_args)
+    }
+
+    // names of variables and called functions in the node, nested functions included
+
+    fn used_names(node: &Node, names: &mut Vec<String>) {
+        if matches!(node.kind, Kind::VAR | Kind::FUNCTION_CALL) {
+            if let Some(Value::STR(name)) = &node.value {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+
+        for op in [&node.op1, &node.op2, &node.op3].into_iter().flatten() {
+            Compiler::used_names(op, names);
+        }
+    }
+
+    // variables of blocks around anonymous function which its body uses, function value gets
+    // their copies when it's created
+
+    fn captures(&self, body: &Node, arguments: &[Value]) -> Vec<(String, Slot)> {
+        let mut names = Vec::new();
+        Compiler::used_names(body, &mut names);
+
+        names
+            .into_iter()
+            .filter(|name| !arguments.contains(&Value::STR(name.clone())))
+            .filter_map(|name| self.block_slot(&name).map(|slot| (name, slot)))
+            .collect()
+    }
+
+    // compiling function body at the current address: captured variables take first locals,
+    // then arguments are bound (last argument is on the top of stack)
+
+    fn compile_function(
+        &mut self,
+        name: String,
+        arguments: &[Value],
+        captures: &[String],
+        body: Node,
+    ) -> Result<(), CompileError> {
+        self.line_table.functions.push((self.pc as usize, name));
+
+        self.scopes.push(FunctionScope {
+            address: self.pc as usize,
+            names: Vec::new(),
+            blocks: self.blocks.len(),
+        });
+        self.blocks.push(Block::default());
+
+        let loops = std::mem::take(&mut self.loops);
+        let result = self.compile_function_body(arguments, captures, body);
+
+        self.loops = loops;
+        self.blocks.pop();
+        let scope = self.scopes.pop().unwrap();
+
+        self.line_table.locals.push(LocalNames {
+            function: scope.address,
+            end: self.pc as usize,
+            names: scope.names,
+        });
+
+        result
+    }
+
+    fn compile_function_body(
+        &mut self,
+        arguments: &[Value],
+        captures: &[String],
+        body: Node,
+    ) -> Result<(), CompileError> {
+        for capture in captures {
+            self.declare(capture)?;
+        }
+
+        for arg in arguments.iter().rev() {
+            let slot = match arg {
+                Value::STR(name) => self.declare(name)?,
+                _ => return Err(self.error(body.span, "Argument name must be alphanumeric!")),
+            };
+
+            self.gen(Compiler::store(slot));
+        }
+
+        self.compile(body)?;
+
+        // function without return statement gives null
+
+        let null = self.constant(Value::NULL);
+
+        self.gen(Operations::PUSH(null));
+        self.gen(Operations::RET);
+
+        Ok(())
+    }
+
+    // compiling loop body, returns its `break` and `continue` jumps
+
+    fn compile_loop_body(&mut self, node: Node) -> Result<LoopContext, CompileError> {
+        self.loops.push(LoopContext::default());
+
+        let result = self.compile_block(node);
+        let context = self.loops.pop().unwrap();
+
+        result.map(|_| context)
+    }
+
+    // compiling body of `if`, `while` or `for` in its own block
+
+    fn compile_block(&mut self, node: Node) -> Result<(), CompileError> {
+        self.blocks.push(Block::default());
+
+        let result = self.compile(node);
+
+        self.blocks.pop();
+        result
+    }
+
+    fn variable_name(&self, node: &Node) -> Result<String, CompileError> {
+        match &node.value {
+            Some(Value::STR(name)) => Ok(name.clone()),
+            _ => Err(self.error(node.span, "Variable name must be alphanumeric!")),
+        }
+    }
+
+    fn fetch(slot: Slot) -> Operations {
+        match slot {
+            Slot::GLOBAL(slot) => Operations::FETCH(slot),
+            Slot::LOCAL(slot) => Operations::FETCH_LOCAL(slot),
+        }
+    }
+
+    fn function_value(function: &crate::vm::Function) -> Value {
+        Value::FUNCTION(Closure {
+            address: function.address,
+            arity: function.arguments.len(),
+            captures: Vec::new(),
+        })
+    }
+
+    fn fetch_item(slot: Slot, depth: usize) -> Operations {
+        match slot {
+            Slot::GLOBAL(slot) => Operations::FETCH_ITEM(slot, depth as u32),
+            Slot::LOCAL(slot) => Operations::FETCH_LOCAL_ITEM(slot, depth as u32),
+        }
+    }
+
+    fn store(slot: Slot) -> Operations {
+        match slot {
+            Slot::GLOBAL(slot) => Operations::STORE(slot),
+            Slot::LOCAL(slot) => Operations::STORE_LOCAL(slot),
+        }
+    }
+
+    // jump instruction, its address is returned for patching
+
+    fn gen_jump(&mut self, operation: Operations) -> i32 {
+        let address = self.pc;
+
+        self.jump_codes.push(address as usize);
+        self.gen(operation);
+
+        address
+    }
+
+    // pointing jump at `address` to the next generated instruction
+
+    fn patch_jump(&mut self, address: i32) {
+        let offset = self.pc - address;
+
+        self.program[address as usize] = match self.program[address as usize] {
+            Operations::JZ(_) => Operations::JZ(offset),
+            Operations::JNZ(_) => Operations::JNZ(offset),
+            _ => Operations::JMP(offset),
+        };
+    }
+
+    // filling operand of `op!` macro operation: value for PUSH, name for variables and number for
+    // arrays and jumps
+
+    fn macro_operation(
+        &mut self,
+        mnemonic: &str,
+        operation: Operations,
+        operand: Node,
+    ) -> Result<Operations, CompileError> {
+        let number = match operand.value {
+            Some(Value::INT(number)) => Some(number),
+            _ => None,
+        };
+
+        let name = match (operand.kind, &operand.value) {
+            (Kind::VAR, Some(Value::STR(name))) => Some(name.clone()),
+            _ => None,
+        };
+
+        let operation = match (operation, name, operand.value.clone(), number) {
+            (Operations::PUSH(_), _, Some(value), _) => Operations::PUSH(self.constant(value)),
+            (Operations::FETCH(_), Some(name), _, _) => Compiler::fetch(self.resolve(&name)?),
+            (Operations::STORE(_), Some(name), _, _) => {
+                Compiler::store(self.resolve_assignment(&name)?)
+            }
+            (Operations::STORE_LOCAL(_), Some(name), _, _) => {
+                Compiler::store(self.declare(&name)?)
+            }
+            (Operations::DROP(_), Some(name), _, _) => match self.resolve(&name)? {
+                Slot::GLOBAL(slot) => Operations::DROP(slot),
+                Slot::LOCAL(slot) => Operations::DROP_LOCAL(slot),
+            },
+            (Operations::ARR(_), _, _, Some(count)) if count >= 0 => Operations::ARR(count as u32),
+            (Operations::DUP(_), _, _, Some(count)) if count >= 0 => Operations::DUP(count as u32),
+            (Operations::CALL_INDIRECT(_), _, _, Some(count)) if count >= 0 => {
+                Operations::CALL_INDIRECT(count as u32)
+            }
+            (Operations::JMP(_), _, _, Some(offset)) => Operations::JMP(offset),
+            (Operations::JZ(_), _, _, Some(offset)) => Operations::JZ(offset),
+            (Operations::JNZ(_), _, _, Some(offset)) => Operations::JNZ(offset),
+            _ => {
+                return Err(self.error(
+                    operand.span,
+                    format!("Wrong operand for '{}' in 'op!' macro!", mnemonic).as_str(),
+                ))
+            }
+        };
+
+        Ok(operation)
+    }
+
+    fn flatten_arguments(node: Node, arguments: &mut Vec<Node>) {
+        match node.kind {
+            Kind::ARGS_ENUM => {
+                if let Some(op1) = node.op1 {
+                    Compiler::flatten_arguments(*op1, arguments);
+                }
+                if let Some(op2) = node.op2 {
+                    Compiler::flatten_arguments(*op2, arguments);
+                }
+            }
+            Kind::EMPTY if node.op1.is_none() => {}
+            _ => arguments.push(node),
+        }
+    }
+
+    fn flatten_elements(node: Node, elements: &mut Vec<Node>) {
+        match node.kind {
+            Kind::BRACK_ENUM => {
+                if let Some(op1) = node.op1 {
+                    Compiler::flatten_elements(*op1, elements);
+                }
+                if let Some(op2) = node.op2 {
+                    Compiler::flatten_elements(*op2, elements);
+                }
+            }
+            Kind::EMPTY if node.op1.is_none() => {}
+            _ => elements.push(node),
+        }
+    }
+
+    fn link(&mut self) -> Result<(), CompileError> {
+        // globals read by functions must be declared somewhere at top level
+
+        for (name, location) in std::mem::take(&mut self.forward_reads) {
+            if !self.globals.get(&name).is_some_and(|global| global.declared) {
+                return Err(CompileError::from(
+                    Diagnostic::error(
+                        Phase::Compiler,
+                        format!("Variable '{}' is not declared!", name),
+                    )
+                    .located(location),
+                ));
+            }
+        }
+
+        // names read before declaration are globals (only in functions) or functions defined later
+
+        for read in std::mem::take(&mut self.forward_values) {
+            let declared = self.globals.get(&read.name).is_some_and(|global| global.declared);
+
+            match self.functions.get(&read.name) {
+                _ if declared && read.slot.is_some() => {}
+                Some(function) => {
+                    let index = self.constant(Compiler::function_value(function));
+                    self.program[read.address] = Operations::PUSH(index);
+                }
+                None => {
+                    return Err(CompileError::from(
+                        Diagnostic::error(
+                            Phase::Compiler,
+                            format!("Variable '{}' is not declared!", read.name),
+                        )
+                        .located(read.location),
+                    ));
+                }
+            }
+        }
+
+        // resolving functions addresses, functions can be called before their definition
+        // (native calls in functions are checked again, function could be defined in next chunk)
+
+        let mut calls = std::mem::take(&mut self.calls);
+        calls.append(&mut self.natives);
+
+        for call in calls {
+            let global = self.globals.get(&call.name).filter(|global| global.declared);
+
+            let function = match (self.functions.get(&call.name), global) {
+                (Some(function), _) => function.clone(),
+
+                // global declared after the call holds function value, top level code runs
+                // before the declaration
+
+                (None, Some(_)) if !call.in_function => {
+                    return Err(CompileError::from(
+                        Diagnostic::error(
+                            Phase::Compiler,
+                            format!("Variable '{}' is not declared!", call.name),
+                        )
+                        .located(call.location),
+                    ));
+                }
+                (None, Some(global)) => {
+                    self.program[call.address] =
+                        Operations::CALL_GLOBAL(global.slot, call.arguments as u32);
+                    continue;
+                }
+                (None, None) => {
+                    // unknown functions are natives, VM checks them before running
+
+                    let name = self.constant(Value::STR(call.name.clone()));
+
+                    self.program[call.address] =
+                        Operations::CALL_NATIVE(name, call.arguments as u32);
+
+                    if call.in_function {
+                        self.natives.push(call);
+                    }
+
+                    continue;
+                }
+            };
+
+            let message = if call.arguments < function.arguments.len() {
+                format!("Not enough arguments for calling '{}' function!", call.name)
+            } else if call.arguments > function.arguments.len() {
+                format!("Too much arguments for '{}' function!", call.name)
+            } else {
+                String::new()
+            };
+
+            if !message.is_empty() {
+                return Err(CompileError::from(
+                    Diagnostic::error(Phase::Compiler, message)
+                        .located(call.location)
+                        .with_note(format!(
+                            "'{}' takes {} argument(s), but {} given",
+                            call.name,
+                            function.arguments.len(),
+                            call.arguments
+                        )),
+                ));
+            }
+
+            self.program[call.address] =
+                Operations::CALL(function.address as u32, call.arguments as u32);
+        }
+
+        Ok(())
+    }
+
+    pub fn byte_code(&self) -> ByteCode {
+        ByteCode {
             program: self.program.clone(),
+            constants: self.constants.clone(),
             functions: self.functions.clone(),
             jump_codes: self.jump_codes.clone(),
-        };
+            line_table: self.line_table.clone(),
+            warnings: self.warnings.clone(),
+        }
+    }
+
+    // Globals set by host program (`VM::set_global`) before running, scripts can use them as
+    // declared variables
+
+    pub fn declare_globals(&mut self, names: &[&str]) -> Result<(), CompileError> {
+        for name in names {
+            self.declare(name)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compile_all(&mut self, nodes: Vec<Node>) -> Result<ByteCode, CompileError> {
+        for n in nodes {
+            self.compile(n)?;
+        }
+
+        self.gen(Operations::HALT);
+        self.link()?;
+
+        Ok(self.byte_code())
+    }
+
+    // Compiling code after already compiled program (used by REPL), chunk ends with its own HALT.
+    // With `keep_result` value of the last expression statement stays on the stack.
+
+    pub fn compile_chunk(
+        &mut self,
+        filename: &str,
+        source: &str,
+        nodes: Vec<Node>,
+        keep_result: bool,
+    ) -> Result<Chunk, CompileError> {
+        self.line_table.files.push(SourceFile {
+            name: filename.to_string(),
+            source: source.to_string(),
+        });
+        self.file = self.line_table.files.len() - 1;
+
+        let start = self.pc as usize;
+        let mut has_result = false;
+        let last_index = nodes.len().saturating_sub(1);
+
+        for (index, node) in nodes.into_iter().enumerate() {
+            let is_result = keep_result
+                && index == last_index
+                && node.kind == Kind::EXPR
+                && matches!(&node.op1, Some(op1) if !matches!(op1.kind, Kind::SET | Kind::EMPTY));
+
+            if is_result {
+                self.compile(*node.op1.unwrap())?;
+                has_result = true;
+            } else {
+                self.compile(node)?;
+            }
+        }
+
+        self.gen(Operations::HALT);
+        self.link()?;
+
+        Ok(Chunk { start, has_result })
+    }
+
+    pub fn compile(&mut self, node: Node) -> Result<(), CompileError> {
+        // every generated slot remembers position of the innermost node with known span
+
+        let parent_span = self.span;
+
+        if node.span.line != 0 {
+            self.span = node.span;
+        }
+
+        let result = self.compile_node(node);
+
+        self.span = parent_span;
+        result
+    }
+
+    fn compile_for(&mut self, node: Node) -> Result<(), CompileError> {
+        // initializating counter variable
+
+        let counter = self.declare("<counter>")?;
+
+        let zero = self.constant(Value::INT(0));
+        self.gen(Operations::PUSH(zero));
+        self.gen(Compiler::store(counter));
+
+        // iterable is computed once and kept with its length (length of array or string, number
+        // itself for `for i in 10`), maps are iterated over their keys
+
+        let iterable = self.declare("<iterable>")?;
+        let length = self.declare("<length>")?;
+
+        self.compile(*node.op1.clone().unwrap())?;
+        self.gen(Operations::ITER);
+        self.gen(Operations::DUP(1));
+        self.gen(Operations::LEN);
+        self.gen(Compiler::store(length));
+        self.gen(Compiler::store(iterable));
+
+        // setting up condition
+
+        let condition_adress = self.pc;
+
+        self.gen(Compiler::fetch(length));
+        self.gen(Compiler::fetch(counter));
+        self.gen(Operations::BT);
+
+        // if condition is true going to the body, otherwise out of loop
+
+        self.gen_jump(Operations::JZ(2));
+        let false_condition_adress = self.gen_jump(Operations::JMP(0));
+
+        // storing variable with item of iterable (declared after compiling iterable, so
+        // `for x in x` iterates over the outer `x`)
+
+        self.gen(Compiler::fetch(counter));
+        self.gen(Compiler::fetch_item(iterable, 1));
+
+        let varname = self.variable_name(&node)?;
+        let varname = self.declare(&varname)?;
+
+        self.gen(Compiler::store(varname));
+
+        // compiling statement block, `continue` goes to increasing counter
+        let context = self.compile_loop_body(*node.op2.clone().unwrap())?;
+
+        for address in context.continues {
+            self.patch_jump(address);
+        }
+
+        // increasing counter variable
+        let one = self.constant(Value::INT(1));
+
+        self.gen(Compiler::fetch(counter));
+        self.gen(Operations::PUSH(one));
+        self.gen(Operations::ADD);
+        self.gen(Compiler::store(counter));
+
+        // returning to condition
+        self.gen_jump(Operations::JMP(condition_adress - self.pc));
+
+        // replacing adresses
+
+        self.patch_jump(false_condition_adress);
+
+        for address in context.breaks {
+            self.patch_jump(address);
+        }
+
+        Ok(())
     }
 
-    pub fn compile(&mut self, node: Node) -> ByteCode {
+    fn compile_node(&mut self, node: Node) -> Result<(), CompileError> {
         match node.kind {
             // Types
             Kind::VAR => {
-                self.gen(Operations::FETCH);
-                self.gen(Operations::ARG(node.value.unwrap()));
-            }
-            Kind::CONST => {
-                self.gen(Operations::PUSH);
-                self.gen(Operations::ARG(node.value.unwrap()));
-            }
-            Kind::STRING => {
-                self.gen(Operations::PUSH);
-                self.gen(Operations::ARG(node.value.unwrap()));
+                let name = self.variable_name(&node)?;
+
+                // name of defined function gives its value, variables hide functions
+
+                match (self.lookup(&name), self.functions.get(&name)) {
+                    (Some(slot), _) => self.gen(Compiler::fetch(slot)),
+                    (None, Some(function)) => {
+                        let index = self.constant(Compiler::function_value(function));
+                        self.gen(Operations::PUSH(index));
+                    }
+
+                    // name is declared later, that is resolved while linking
+
+                    (None, None) => {
+                        let slot = match self.scopes.is_empty() {
+                            true => None,
+                            false => Some(self.global_slot(&name)?),
+                        };
+
+                        self.forward_values.push(ForwardValue {
+                            address: self.pc as usize,
+                            name,
+                            slot,
+                            location: self.location(node.span),
+                        });
+
+                        match slot {
+                            Some(slot) => self.gen(Operations::FETCH(slot)),
+                            None => {
+                                let null = self.constant(Value::NULL);
+                                self.gen(Operations::PUSH(null));
+                            }
+                        }
+                    }
+                }
             }
-            Kind::BOOL => {
-                self.gen(Operations::PUSH);
-                self.gen(Operations::ARG(node.value.unwrap()));
+            Kind::CONST | Kind::STRING | Kind::BOOL | Kind::NULL => {
+                let value = self.constant(node.value.unwrap());
+                self.gen(Operations::PUSH(value));
             }
             Kind::ARRAY => {
-                self.gen(Operations::CLEAN);
+                let mut elements = Vec::new();
+                Compiler::flatten_elements(*node.op1.clone().unwrap(), &mut elements);
 
-                self.compile(*node.op1.clone().unwrap());
+                let elements_count = elements.len();
 
-                self.gen(Operations::ARR);
+                for element in elements {
+                    self.compile(element)?;
+                }
+
+                self.gen(Operations::ARR(elements_count as u32));
+            }
+            Kind::MAP => {
+                let mut elements = Vec::new();
+                Compiler::flatten_elements(*node.op1.clone().unwrap(), &mut elements);
+
+                let pairs_count = elements.len() / 2;
+
+                for element in elements {
+                    self.compile(element)?;
+                }
+
+                self.gen(Operations::MAP(pairs_count as u32));
             }
 
             // Operations
             Kind::ADD => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::ADD);
             }
             Kind::SUB => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::SUB);
             }
             Kind::MULT => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::MULT);
             }
             Kind::DIV => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::DIV);
             }
+            Kind::SET if node.op1.as_ref().unwrap().kind == Kind::SLICE => {
+                // `a[i][j] = x` changes item of existing array
+
+                let (name, indexes) = self.assignment_target(*node.op1.clone().unwrap())?;
+                let slot = self.resolve(&name)?;
+                let depth = indexes.len() - 1;
+
+                for index in indexes {
+                    self.compile(index)?;
+                }
+
+                self.compile(*node.op2.clone().unwrap())?;
+                self.edit(slot, depth, Operations::SLICE_SET);
+            }
             Kind::SET => {
-                self.compile(*node.op2.clone().unwrap());
-                self.gen(Operations::STORE);
-                self.gen(Operations::ARG(node.op1.clone().unwrap().value.unwrap()));
+                self.compile(*node.op2.clone().unwrap())?;
+
+                let name = self.variable_name(node.op1.as_ref().unwrap())?;
+                let slot = self.resolve_assignment(&name)?;
+
+                self.gen(Compiler::store(slot));
             }
 
             // Functions and Constructions
             Kind::PRINT => {
-                self.compile(*node.op1.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
                 self.gen(Operations::PRINT);
 
                 if let Some(node_2) = node.op2.clone() {
-                    self.compile(*node_2);
+                    self.compile(*node_2)?;
                 }
                 if let Some(node_3) = node.op3.clone() {
-                    self.compile(*node_3);
+                    self.compile(*node_3)?;
                 }
             }
             Kind::INPUT => {
@@ -171,461 +1220,498 @@ impl Compiler {
             }
 
             Kind::IF => {
-                self.compile(*node.op1.clone().unwrap());
-
-                self.gen(Operations::JZ);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(4)));
+                self.compile(*node.op1.clone().unwrap())?;
 
-                let else_adress = self.pc;
+                self.gen_jump(Operations::JZ(2));
+                let else_adress = self.gen_jump(Operations::JMP(0));
 
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(0)));
+                self.compile_block(*node.op2.clone().unwrap())?;
 
-                self.compile(*node.op2.clone().unwrap());
-
-                let after_adress = self.pc;
-
-                self.program[(else_adress + 1) as usize] =
-                    Operations::ARG(Value::INT(after_adress - else_adress));
+                self.patch_jump(else_adress);
             }
             Kind::IF_ELSE => {
-                self.compile(*node.op1.clone().unwrap());
-
-                self.gen(Operations::JZ);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(4)));
-
-                let else_jmp_adress = self.pc;
-
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(0)));
-
-                self.compile(*node.op2.clone().unwrap());
-
-                let complete_adress = self.pc;
-
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(0)));
+                self.compile(*node.op1.clone().unwrap())?;
 
-                let else_adress = self.pc;
+                self.gen_jump(Operations::JZ(2));
+                let else_jmp_adress = self.gen_jump(Operations::JMP(0));
 
-                self.compile(*node.op3.clone().unwrap());
+                self.compile_block(*node.op2.clone().unwrap())?;
 
-                let after_adress = self.pc;
+                let complete_adress = self.gen_jump(Operations::JMP(0));
 
-                self.program[(else_jmp_adress + 1) as usize] =
-                    Operations::ARG(Value::INT(else_adress - else_jmp_adress));
-
-                self.program[(complete_adress + 1) as usize] =
-                    Operations::ARG(Value::INT(after_adress - complete_adress));
+                self.patch_jump(else_jmp_adress);
+                self.compile_block(*node.op3.clone().unwrap())?;
+                self.patch_jump(complete_adress);
             }
             Kind::WHILE => {
                 let condition_adress = self.pc;
 
-                self.compile(*node.op1.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
 
-                self.gen(Operations::JZ);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(4)));
+                self.gen_jump(Operations::JZ(2));
+                let false_jmp_adress = self.gen_jump(Operations::JMP(0));
 
-                let false_jmp_adress = self.pc;
+                let context = self.compile_loop_body(*node.op2.clone().unwrap())?;
 
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(0)));
+                // `continue` goes to the jump back to condition
 
-                self.compile(*node.op2.clone().unwrap());
+                for address in context.continues {
+                    self.patch_jump(address);
+                }
 
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(condition_adress - self.pc + 1)));
+                self.gen_jump(Operations::JMP(condition_adress - self.pc));
+                self.patch_jump(false_jmp_adress);
 
-                self.program[(false_jmp_adress + 1) as usize] =
-                    Operations::ARG(Value::INT(self.pc - false_jmp_adress));
+                for address in context.breaks {
+                    self.patch_jump(address);
+                }
             }
             Kind::FOR => {
-                // initializating counter variable
-
-                let counter_name = format!("counter{}", self.pc);
-                let varname = node.value.unwrap();
+                // counter and loop variable live in the loop block, counter name can't be
+                // written in code
 
-                self.gen(Operations::PUSH);
-                self.gen(Operations::ARG(Value::INT(0)));
+                self.blocks.push(Block::default());
 
-                self.gen(Operations::STORE);
-                self.gen(Operations::ARG(Value::STR(counter_name.clone())));
+                let result = self.compile_for(node.clone());
 
-                // setting up condition
+                self.blocks.pop();
+                result?;
+            }
+            Kind::FUNCTION_DEFINE => {
+                let function_name = match node.value {
+                    Some(Value::STR(name)) => name,
+                    _ => {
+                        return Err(self.error(node.span, "Function name must be alphanumeric!"));
+                    }
+                };
 
-                let condition_adress = self.pc;
+                // formatting args
 
-                self.compile(*node.op1.clone().unwrap());
+                let formatted_args =
+                    self.function_arguments(&function_name, *node.op1.clone().unwrap())?;
 
-                self.gen(Operations::FETCH);
-                self.gen(Operations::ARG(Value::STR(counter_name.clone())));
+                if self.globals.get(&function_name).is_some_and(|global| global.declared) {
+                    return Err(self.error(
+                        node.span,
+                        format!("Name '{}' is already used by variable!", function_name).as_str(),
+                    ));
+                }
 
-                self.gen(Operations::BT);
+                if self.functions.contains_key(&function_name) {
+                    self.warning(
+                        node.span,
+                        format!("Function '{}' is defined more than once", function_name).as_str(),
+                        "all calls use the last definition",
+                    );
+                }
 
-                // if condition is true
+                // jumping over function body
 
-                self.gen(Operations::JZ);
+                let skip_adress = self.gen_jump(Operations::JMP(0));
 
-                self.jump_codes.push(self.pc as usize);
+                // creating function object before body for recursive calls
 
-                self.gen(Operations::ARG(Value::INT(4)));
+                let function = crate::vm::Function {
+                    name: Value::STR(function_name.clone()),
+                    arguments: formatted_args.clone(),
+                    address: self.pc as usize,
+                };
 
-                // if condition is false
+                self.functions.insert(function_name.clone(), function);
 
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
+                self.compile_function(function_name, &formatted_args, &[], *node.op2.unwrap())?;
+                self.patch_jump(skip_adress);
+            }
+            Kind::LAMBDA => {
+                let formatted_args =
+                    self.function_arguments("<lambda>", *node.op1.clone().unwrap())?;
 
-                let false_condition_adress = self.pc;
-                self.gen(Operations::ARG(Value::INT(0)));
+                let body = *node.op2.clone().unwrap();
+                let captures = self.captures(&body, &formatted_args);
+                let names = captures
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<String>>();
 
-                // storing variable with slice from iterable
-                self.compile(*node.op1.clone().unwrap());
-                self.gen(Operations::FETCH);
-                self.gen(Operations::ARG(Value::STR(counter_name.clone())));
+                let skip_adress = self.gen_jump(Operations::JMP(0));
+                let address = self.pc as usize;
 
-                self.gen(Operations::SLICE);
+                self.compile_function("<lambda>".to_string(), &formatted_args, &names, body)?;
+                self.patch_jump(skip_adress);
 
-                self.gen(Operations::STORE);
-                self.gen(Operations::ARG(varname));
+                // function value is created from captured values on the stack
 
-                // compiling statement block
-                self.compile(*node.op2.clone().unwrap());
+                for (_, slot) in &captures {
+                    self.gen(Compiler::fetch(*slot));
+                }
 
-                // increasing counter variable
-                self.gen(Operations::FETCH);
-                self.gen(Operations::ARG(Value::STR(counter_name.clone())));
+                let function = self.constant(Value::FUNCTION(Closure {
+                    address,
+                    arity: formatted_args.len(),
+                    captures: Vec::new(),
+                }));
 
-                self.gen(Operations::PUSH);
-                self.gen(Operations::ARG(Value::INT(1)));
+                self.gen(Operations::CLOSURE(function, captures.len() as u32));
+            }
+            Kind::INDIRECT_CALL => {
+                let mut arguments = Vec::new();
+                Compiler::flatten_arguments(*node.op2.clone().unwrap(), &mut arguments);
 
-                self.gen(Operations::ADD);
+                let arguments_count = arguments.len();
 
-                self.gen(Operations::STORE);
-                self.gen(Operations::ARG(Value::STR(counter_name.clone())));
+                for argument in arguments {
+                    self.compile(argument)?;
+                }
 
-                // returning to condition
-                self.gen(Operations::JMP);
-                self.jump_codes.push(self.pc as usize);
-                self.gen(Operations::ARG(Value::INT(condition_adress - self.pc + 1)));
+                self.compile(*node.op1.clone().unwrap())?;
+                self.gen(Operations::CALL_INDIRECT(arguments_count as u32));
+            }
+            // method call (`a.push(x)`) of array method
 
-                // replacing adresses
+            Kind::FUNCTION_CALL
+                if node.op2.is_some() && Compiler::array_method(&node).is_some() =>
+            {
+                let (method, operation, arity) = Compiler::array_method(&node).unwrap();
 
-                self.program[false_condition_adress as usize] =
-                    Operations::ARG(Value::INT(self.pc - false_condition_adress + 1));
+                self.compile_array_method(node.clone(), method, operation, arity)?;
             }
-            Kind::FUNCTION_DEFINE => {
-                let function_name = node.value.unwrap();
+            Kind::FUNCTION_CALL => {
+                if let Some(Value::STR(function_name)) = node.value.clone() {
+                    // compiling arguments (`a.foo(b)` passes `a` as first argument)
 
-                // getting compiled expressions
+                    let mut arguments = Vec::new();
 
-                let mut args_compiler = Compiler::new();
-                let args_bytes = args_compiler
-                    .compile(*node.op1.clone().unwrap())
-                    .program
-                    .into_iter()
-                    .filter(|x| x != &Operations::FETCH)
-                    .collect::<Vec<Operations>>();
+                    if let Some(node_1) = node.op1 {
+                        Compiler::flatten_arguments(*node_1, &mut arguments);
+                    }
+                    if let Some(node_2) = node.op2 {
+                        Compiler::flatten_arguments(*node_2, &mut arguments);
+                    }
 
-                let mut program_compiler = Compiler::new();
+                    let arguments_count = arguments.len();
 
-                for func in self.functions.clone() {
-                    program_compiler.functions.insert(func.0, func.1);
-                }
+                    for argument in arguments {
+                        self.compile(argument)?;
+                    }
 
-                // Compiling
+                    // variable holding function value is called indirectly, local variables hide
+                    // functions with the same name
 
-                let program_bytes = program_compiler
-                    .compile(*node.op2.clone().unwrap())
-                    .program
-                    .into_iter()
-                    .filter(|x| x != &Operations::HALT)
-                    .collect::<Vec<Operations>>();
+                    let variable = match self.block_slot(&function_name) {
+                        Some(slot) => Some(slot),
+                        None if self.functions.contains_key(&function_name) => None,
+                        None => self.lookup(&function_name),
+                    };
 
-                // formatting args
+                    if let Some(slot) = variable {
+                        self.gen(Compiler::fetch(slot));
+                        self.gen(Operations::CALL_INDIRECT(arguments_count as u32));
 
-                let mut formatted_args: Vec<Value> = Vec::new();
+                        return Ok(());
+                    }
 
-                for arg in args_bytes {
-                    match arg {
-                        Operations::ARG(val) => {
-                            formatted_args.push(val);
-                        }
-                        _ => {}
+                    self.calls.push(FunctionCall {
+                        address: self.pc as usize,
+                        name: function_name,
+                        arguments: arguments_count,
+                        location: self.location(node.span),
+                        in_function: !self.scopes.is_empty(),
+                    });
+
+                    self.gen(Operations::CALL(0, arguments_count as u32));
+                }
+            }
+
+            Kind::RETURN => {
+                if self.scopes.is_empty() {
+                    return Err(self.error(node.span, "Cannot use 'return' outside of function!"));
+                }
+
+                match node.op1 {
+                    Some(op1) => self.compile(*op1)?,
+                    None => {
+                        let null = self.constant(Value::NULL);
+                        self.gen(Operations::PUSH(null));
                     }
                 }
 
-                // creating function object
+                self.gen(Operations::RET);
+            }
 
-                let function = crate::vm::Function {
-                    name: function_name.clone(),
-                    arguments: formatted_args,
-                    program: program_bytes,
-                    jump_codes: program_compiler.jump_codes,
-                };
+            Kind::BREAK | Kind::CONTINUE => {
+                if self.loops.is_empty() {
+                    return Err(self.error(
+                        node.span,
+                        "Cannot use 'break' or 'continue' outside of loop!",
+                    ));
+                }
 
-                let stringify_function_name = match function_name {
-                    Value::STR(str) => str,
-                    _ => "undef".to_string(),
-                };
+                let address = self.gen_jump(Operations::JMP(0));
+                let context = self.loops.last_mut().unwrap();
 
-                self.functions.insert(stringify_function_name, function);
+                match node.kind {
+                    Kind::BREAK => context.breaks.push(address),
+                    _ => context.continues.push(address),
+                }
             }
-            Kind::FUNCTION_CALL => {
-                if let Some(Value::STR(val)) = node.value {
-                    let function_name = val;
-
-                    if self.functions.contains_key(&function_name) {
-                        // Initializating function object
-
-                        let compiler_clone = self.clone();
-                        let mut function_object = compiler_clone
-                            .functions
-                            .get(&function_name)
-                            .unwrap()
-                            .clone();
-
-                        // Implementing arguments
-
-                        let mut compiler1 = Compiler::new();
-                        let mut args_node_1 = compiler1
-                            .compile(*node.op1.clone().unwrap())
-                            .program
-                            .into_iter()
-                            .collect::<Vec<Operations>>();
-
-                        let mut args_bytes = Vec::new();
-                        args_bytes.append(&mut args_node_1);
-
-                        // Checking for second node
-
-                        if let Some(node_2) = node.op2.clone() {
-                            let mut compiler2 = Compiler::new();
-                            let mut args_node_2 = compiler2
-                                .compile(*node_2)
-                                .program
-                                .into_iter()
-                                .collect::<Vec<Operations>>();
-
-                            args_bytes.append(&mut args_node_2);
-                        }
 
-                        // Formatting and comparing args
+            Kind::BRACK_ENUM => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+            }
+            Kind::ARGS_ENUM => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+            }
+            Kind::SLICE => {
+                // item of variable `a[i][j]` is read without copying the variable
 
-                        let args_length = args_bytes.len();
+                let mut indexes = Vec::new();
+                let mut container = &node;
 
-                        if args_length / 2 < function_object.arguments.len() {
-                            if let Value::STR(func_name) = function_object.clone().name {
-                                self.error(
-                                    format!(
-                                        "Not enough arguments for calling '{}' function!",
-                                        func_name
-                                    )
-                                    .as_str(),
-                                );
-                            }
-                        } else if args_length / 2 > function_object.arguments.len() {
-                            if let Value::STR(func_name) = function_object.clone().name {
-                                self.error(
-                                    format!("Too much arguments for '{}' function!", func_name)
-                                        .as_str(),
-                                );
-                            }
-                        }
+                while container.kind == Kind::SLICE {
+                    indexes.push(*container.op2.clone().unwrap());
+                    container = container.op1.as_ref().unwrap();
+                }
 
-                        self.program.append(&mut args_bytes);
+                let slot = match (&container.kind, &container.value) {
+                    (Kind::VAR, Some(Value::STR(name))) => self.lookup(name),
+                    _ => None,
+                };
 
-                        // Generating variables for arguments
+                match slot {
+                    Some(slot) => {
+                        let depth = indexes.len();
 
-                        for (_, arg) in function_object.arguments.iter().rev().enumerate() {
-                            self.gen(Operations::STORE);
-                            self.program.push(Operations::ARG(arg.clone()));
+                        for index in indexes.into_iter().rev() {
+                            self.compile(index)?;
                         }
 
-                        let mut function_program = function_object.program.clone();
-
-                        self.program.append(&mut function_program);
+                        self.gen(Compiler::fetch_item(slot, depth));
+                    }
+                    None => {
+                        self.compile(*node.op1.clone().unwrap())?;
+                        self.compile(*node.op2.clone().unwrap())?;
 
-                        function_object.arguments.iter().for_each(|arg| {
-                            self.gen(Operations::DROP);
-                            self.gen(Operations::ARG(arg.clone()))
-                        });
-                    } else {
-                        self.error(
-                            format!("Function '{}' is not defined here!", &function_name).as_str(),
-                        );
+                        self.gen(Operations::SLICE);
                     }
                 }
             }
 
-            Kind::BRACK_ENUM => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
-            }
-            Kind::ARGS_ENUM => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
-            }
-            Kind::SLICE => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+            Kind::OP_MACRO => {
+                let mut arguments = Vec::new();
+                Compiler::flatten_arguments(*node.op1.clone().unwrap(), &mut arguments);
 
-                self.gen(Operations::SLICE);
-            }
+                let mut arguments = arguments.into_iter();
 
-            Kind::OP_MACRO => {
-                let mut args_compiler = Compiler::new();
-                let arguments = args_compiler
-                    .compile(*node.op1.clone().unwrap())
-                    .program
-                    .into_iter()
-                    .filter(|x| x != &Operations::FETCH && x != &Operations::PUSH)
-                    .collect::<Vec<Operations>>();
-
-                for arg in arguments {
-                    if let Operations::ARG(Value::STR(string_argument)) = arg {
-                        if OPERATIONS_MAP.contains_key(&string_argument.as_str()) {
-                            let matched_operation =
-                                OPERATIONS_MAP.get(&string_argument.as_str()).unwrap();
-
-                            if [Operations::JZ, Operations::JNZ, Operations::JMP]
-                                .contains(matched_operation)
-                            {
-                                self.gen(matched_operation.clone());
-                                self.jump_codes.push(self.pc as usize);
-                            } else {
-                                self.gen(matched_operation.clone());
-                            }
-                        } else {
-                            self.gen(Operations::ARG(Value::STR(string_argument)));
+                while let Some(argument) = arguments.next() {
+                    let mnemonic = match (&argument.kind, &argument.value) {
+                        (Kind::VAR, Some(Value::STR(name))) => name.clone(),
+                        _ => String::new(),
+                    };
+
+                    let operation = OPERATIONS_MAP.get(mnemonic.as_str());
+
+                    let operation = match operation {
+                        Some(operation) => *operation,
+                        None => {
+                            return Err(
+                                self.error(argument.span, "Expected operation name in 'op!' macro!")
+                            )
+                        }
+                    };
+
+                    // operations with operand take the next argument
+
+                    let operation = match operation {
+                        Operations::PUSH(_)
+                        | Operations::ARR(_)
+                        | Operations::FETCH(_)
+                        | Operations::STORE(_)
+                        | Operations::STORE_LOCAL(_)
+                        | Operations::DROP(_)
+                        | Operations::DUP(_)
+                        | Operations::CALL_INDIRECT(_)
+                        | Operations::JMP(_)
+                        | Operations::JZ(_)
+                        | Operations::JNZ(_) => {
+                            let operand = match arguments.next() {
+                                Some(operand) => operand,
+                                None => {
+                                    return Err(self.error(
+                                        argument.span,
+                                        format!("'{}' in 'op!' macro expects operand!", mnemonic)
+                                            .as_str(),
+                                    ))
+                                }
+                            };
+
+                            self.macro_operation(&mnemonic, operation, operand)?
                         }
+                        _ => operation,
+                    };
+
+                    if matches!(
+                        operation,
+                        Operations::JMP(_) | Operations::JZ(_) | Operations::JNZ(_)
+                    ) {
+                        self.gen_jump(operation);
                     } else {
-                        self.gen(arg);
+                        self.gen(operation);
                     }
                 }
             }
 
             Kind::FILE_IMPORT => {
-                if let Some(Value::STR(_str)) = node.value {
+                if let Some(Value::STR(_str)) = node.value.clone() {
                     // finding file
-                    let _filepath = crate::filereader::search_import(_str.clone());
+                    let _filepath = crate::filereader::search_import(_str.clone())
+                        .map_err(|message| self.error(node.span, &message))?;
 
-                    if _filepath == "FILE_NOT_FOUND_1_HIW_ERROR" {
-                        self.error(format!("Import '{}' not found!", _str).as_str());
-                    }
-
-                    let _source = crate::filereader::get_code(_filepath);
+                    let _source = crate::filereader::get_code(_filepath).map_err(|e| {
+                        let message = format!("Import '{}' cannot be opened: {}", _str, e);
+                        self.error(node.span, &message)
+                    })?;
 
                     // compiling source code
 
-                    let _lexer = crate::lexer::Lexer::new(_source, _str);
-
-                    let mut lexer_clone = _lexer.clone();
-                    while lexer_clone.token != Some(crate::lexer::Token::EOF) {
-                        lexer_clone.next_token();
-                    }
-
-                    if lexer_clone.errors.len() > 0 {
-                        for err in lexer_clone.errors {
-                            eprintln!("{}", err);
-                        }
-                        std::process::exit(1);
-                    }
+                    let _lexer = crate::lexer::Lexer::new(_source.clone(), _str.clone());
+                    _lexer.check()?;
 
                     let mut _parser = crate::parser::Parser::new(_lexer);
-                    let _ast = _parser.parse();
+                    let _ast = _parser.parse()?;
 
-                    if _parser.errors.len() > 0 {
-                        for err in _parser.errors {
-                            eprintln!("{}", err);
-                            std::process::exit(1);
-                        }
-                    }
+                    // compiling imported module right into the current program, so its
+                    // functions addresses stay valid
 
-                    let mut _compiler = crate::compiler::Compiler::new();
-                    let _byte_code = _compiler.compile_all(_ast);
+                    let parent_file = self.file;
 
-                    // for first copying functions to the main byte code
+                    self.line_table.files.push(SourceFile {
+                        name: _str,
+                        source: _source,
+                    });
+                    self.file = self.line_table.files.len() - 1;
 
-                    for func in _byte_code.functions {
-                        self.functions.insert(func.0, func.1);
+                    for _node in _ast {
+                        self.compile(_node)?;
                     }
 
-                    // next format imported program to main
-
-                    let mut program_object = _byte_code.program;
-
-                    // deleting HALT for continue the program
-                    let _ = program_object.pop();
-
-                    // now we can attach it to the current
-
-                    self.program.append(&mut program_object);
+                    self.file = parent_file;
                 }
             }
 
             // Conditions
             Kind::LT => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::LT);
             }
             Kind::BT => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::BT);
             }
             Kind::EQ => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
                 self.gen(Operations::EQ);
             }
+            Kind::NE => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+                self.gen(Operations::NE);
+            }
+            Kind::LE => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+                self.gen(Operations::LE);
+            }
+            Kind::BE => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+                self.gen(Operations::BE);
+            }
+
+            // Logical
+            Kind::AND | Kind::OR => {
+                // second operand is computed only if first one doesn't decide the result:
+                // `&&` stops on false and `||` stops on true (JNZ jumps on false, JZ on true)
+
+                let stop = match node.kind {
+                    Kind::AND => Operations::JNZ(0),
+                    _ => Operations::JZ(0),
+                };
+                let decided = node.kind == Kind::OR;
+
+                self.compile(*node.op1.clone().unwrap())?;
+                let first_jump = self.gen_jump(stop);
+
+                self.compile(*node.op2.clone().unwrap())?;
+                let second_jump = self.gen_jump(stop);
+
+                let other = self.constant(Value::BOOL(!decided));
+                self.gen(Operations::PUSH(other));
+                let end_jump = self.gen_jump(Operations::JMP(0));
+
+                self.patch_jump(first_jump);
+                self.patch_jump(second_jump);
+
+                let decided = self.constant(Value::BOOL(decided));
+                self.gen(Operations::PUSH(decided));
+
+                self.patch_jump(end_jump);
+            }
+            Kind::NOT => {
+                self.compile(*node.op1.clone().unwrap())?;
+                self.gen(Operations::NOT);
+            }
 
             // Etc.
             Kind::EMPTY => {
                 if let Some(op1) = node.op1 {
-                    self.compile(*op1);
+                    self.compile(*op1)?;
                 }
                 if let Some(op2) = node.op2 {
-                    self.compile(*op2);
+                    self.compile(*op2)?;
                 }
                 if let Some(op3) = node.op3 {
-                    self.compile(*op3);
+                    self.compile(*op3)?;
                 }
             }
             Kind::SEQ => {
-                self.compile(*node.op1.clone().unwrap());
-                self.compile(*node.op2.clone().unwrap());
+                self.compile(*node.op1.clone().unwrap())?;
+                self.compile(*node.op2.clone().unwrap())?;
+            }
+            Kind::EXPR => {
+                if let Some(op1) = node.op1 {
+                    let produces_value = !matches!(op1.kind, Kind::SET | Kind::EMPTY);
+
+                    self.compile(*op1)?;
+
+                    // dropping unused result (for example from function call)
+
+                    if produces_value {
+                        self.gen(Operations::POP);
+                    }
+                }
             }
-            Kind::PROG | Kind::EXPR => {
+            Kind::PROG => {
                 if let Some(op1) = node.op1 {
-                    self.compile(*op1);
+                    self.compile(*op1)?;
                 }
                 if let Some(op2) = node.op2 {
-                    self.compile(*op2);
+                    self.compile(*op2)?;
                 }
                 if let Some(op3) = node.op3 {
-                    self.compile(*op3);
+                    self.compile(*op3)?;
                 }
             }
-            _ => {}
         }
 
         if node.kind == Kind::PROG {
             self.gen(Operations::HALT);
         }
 
-        ByteCode {
-            program: self.program.clone(),
-            functions: self.functions.clone(),
-            jump_codes: self.jump_codes.clone(),
-        }
+        Ok(())
     }
 }
@@ -0,0 +1,212 @@
+// hiw-lang compiler
+// https://github.com/mealet/hiw-lang
+// ----------------------------------------
+// Copyright ©️ 2024, mealet.
+// Project licensed under the BSD-3 License
+// that can be found in LICENSE file.
+// ----------------------------------------
+
+// Diagnostic - shared error type for every phase, knows where the problem is and how to show it.
+
+use colored::{Color, Colorize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Lexer,
+    Parser,
+    Compiler,
+    Loader,
+    Runtime,
+}
+
+// Position in the source: line and columns range [start, end), everything counts from 1
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub span: Span,
+    pub source_line: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    pub message: String,
+    pub location: Option<Location>,
+    pub notes: Vec<String>,
+}
+
+impl Span {
+    pub fn new(line: usize, start: usize, end: usize) -> Self {
+        Span {
+            line,
+            start,
+            end: end.max(start + 1),
+        }
+    }
+}
+
+impl Location {
+    pub fn new(file: &str, source: &str, span: Span) -> Self {
+        let source_line = source
+            .lines()
+            .nth(span.line.saturating_sub(1))
+            .unwrap_or("")
+            .to_string();
+
+        Location {
+            file: file.to_string(),
+            span,
+            source_line,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Lexer => "Lexer",
+            Phase::Parser => "Parser",
+            Phase::Compiler => "Compiler",
+            Phase::Loader => "Loader",
+            Phase::Runtime => "Runtime",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, phase: Phase, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            phase,
+            message: message.into(),
+            location: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(phase: Phase, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, phase, message)
+    }
+
+    pub fn warning(phase: Phase, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, phase, message)
+    }
+
+    // attaching position, source line is taken from the whole file source
+
+    pub fn at(self, file: &str, source: &str, span: Span) -> Self {
+        self.located(Location::new(file, source, span))
+    }
+
+    pub fn located(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // colored output for terminal, `Display` gives the same text without colors
+
+    pub fn render(&self) -> String {
+        self.format(true)
+    }
+
+    fn format(&self, colors: bool) -> String {
+        let paint = |text: &str, color: Color| match colors {
+            true => text.color(color),
+            false => text.normal(),
+        };
+
+        let (label, color) = match self.severity {
+            Severity::Error => ("error", Color::Red),
+            Severity::Warning => ("warning", Color::Yellow),
+        };
+
+        let header = format!("{}[{}]", label, self.phase);
+        let header = match colors {
+            true => header.color(color).bold(),
+            false => header.normal(),
+        };
+
+        let mut output = format!("{}: {}", header, self.message);
+
+        let gutter = match &self.location {
+            Some(location) => location.span.line.to_string().len() + 1,
+            None => 2,
+        };
+        let padding = " ".repeat(gutter);
+        let bar = paint("|", Color::Cyan);
+
+        if let Some(location) = &self.location {
+            let span = location.span;
+
+            output.push_str(&format!(
+                "\n{}{}{}:{}:{}",
+                " ".repeat(gutter - 1),
+                paint("--> ", Color::Cyan),
+                location.file,
+                span.line,
+                span.start
+            ));
+            output.push_str(&format!("\n{}{}", padding, bar));
+            output.push_str(&format!(
+                "\n{} {}",
+                paint(&format!("{:>width$} |", span.line, width = gutter - 1), Color::Cyan),
+                location.source_line
+            ));
+
+            // underline keeps tabs from the source line, so carets stay under the span
+
+            let line_length = location.source_line.chars().count();
+            let start = span.start.clamp(1, line_length + 1);
+            let end = span.end.clamp(start + 1, line_length.max(start) + 1);
+
+            let indent = location
+                .source_line
+                .chars()
+                .take(start - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+
+            output.push_str(&format!(
+                "\n{}{} {}{}",
+                padding,
+                bar,
+                indent,
+                paint(&"^".repeat(end - start), color)
+            ));
+        }
+
+        for note in &self.notes {
+            output.push_str(&format!("\n{}{} note: {}", padding, paint("=", Color::Cyan), note));
+        }
+
+        output
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(false))
+    }
+}
@@ -0,0 +1,173 @@
+// hiw-lang compiler
+// https://github.com/mealet/hiw-lang
+// ----------------------------------------
+// Copyright ©️ 2024, mealet.
+// Project licensed under the BSD-3 License
+// that can be found in LICENSE file.
+// ----------------------------------------
+
+// Disassembler - readable listing of byte code for debugging the compiler
+
+use crate::{
+    compiler::ByteCode,
+    vm::{Operations, Value},
+};
+use std::fmt::Write;
+
+fn constant(byte_code: &ByteCode, index: u32) -> String {
+    match byte_code.constants.get(index as usize) {
+        Some(value) => format!("{:?}", value),
+        None => format!("#{}?", index),
+    }
+}
+
+fn operands(operation: &Operations, address: usize, byte_code: &ByteCode) -> String {
+    match *operation {
+        Operations::PUSH(index) => constant(byte_code, index),
+        Operations::FETCH(slot) | Operations::STORE(slot) | Operations::DROP(slot) => {
+            let global = byte_code.line_table.globals.get(slot as usize);
+            variable(slot, global.map(|global| &global.name))
+        }
+        Operations::FETCH_LOCAL(slot)
+        | Operations::STORE_LOCAL(slot)
+        | Operations::DROP_LOCAL(slot) => variable(slot, local(byte_code, address, slot)),
+
+        // variable and count of indexes (arguments)
+
+        Operations::FETCH_ITEM(slot, count)
+        | Operations::EDIT(slot, count)
+        | Operations::CALL_GLOBAL(slot, count) => {
+            let global = byte_code.line_table.globals.get(slot as usize);
+            format!("{}, {}", variable(slot, global.map(|global| &global.name)), count)
+        }
+        Operations::FETCH_LOCAL_ITEM(slot, count) | Operations::EDIT_LOCAL(slot, count) => {
+            format!("{}, {}", variable(slot, local(byte_code, address, slot)), count)
+        }
+        Operations::ARR(count)
+        | Operations::MAP(count)
+        | Operations::DUP(count)
+        | Operations::CALL_INDIRECT(count) => count.to_string(),
+
+        // jumps are relative to the jump instruction
+
+        Operations::JMP(offset) | Operations::JZ(offset) | Operations::JNZ(offset) => {
+            format!("{:+} -> {:04}", offset, address as i64 + offset as i64)
+        }
+        Operations::CALL(target, argc) => {
+            match byte_code
+                .functions
+                .values()
+                .find(|function| function.address == target as usize)
+            {
+                Some(function) => format!("{:04} <{}>, {}", target, name(&function.name), argc),
+                None => format!("{:04}, {}", target, argc),
+            }
+        }
+        Operations::CALL_NATIVE(native, argc) => {
+            format!("{}, {}", constant(byte_code, native), argc)
+        }
+        Operations::CLOSURE(index, count) => match byte_code.constants.get(index as usize) {
+            Some(Value::FUNCTION(function)) => {
+                let name = byte_code.line_table.function_name(function.address);
+                format!("{:04} <{}>, {}", function.address, name.unwrap_or("?"), count)
+            }
+            _ => format!("{}, {}", constant(byte_code, index), count),
+        },
+        _ => String::new(),
+    }
+}
+
+// locals belong to the innermost function around the instruction
+
+fn local(byte_code: &ByteCode, address: usize, slot: u32) -> Option<&String> {
+    byte_code
+        .line_table
+        .locals
+        .iter()
+        .filter(|locals| locals.function <= address && address < locals.end)
+        .max_by_key(|locals| locals.function)
+        .and_then(|locals| locals.names.get(slot as usize))
+}
+
+fn variable(slot: u32, name: Option<&String>) -> String {
+    match name {
+        Some(name) => format!("{} ({})", slot, name),
+        None => slot.to_string(),
+    }
+}
+
+fn mnemonic(operation: &Operations) -> String {
+    let debug = format!("{:?}", operation);
+
+    match debug.split_once('(') {
+        Some((mnemonic, _)) => mnemonic.to_string(),
+        None => debug,
+    }
+}
+
+fn name(value: &Value) -> String {
+    match value {
+        Value::STR(name) => name.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+pub fn disassemble(byte_code: &ByteCode) -> String {
+    let line_table = &byte_code.line_table;
+
+    let mut functions = byte_code.functions.values().collect::<Vec<_>>();
+    functions.sort_by_key(|function| function.address);
+
+    let mut output = String::from("== code ==\n");
+
+    for (address, operation) in byte_code.program.iter().enumerate() {
+        // anonymous functions are only in the line table
+
+        if let Some(function) = functions.iter().find(|f| f.address == address) {
+            let _ = writeln!(output, "\n<{}>:", name(&function.name));
+        } else if let Some(function) = line_table.function_name(address) {
+            let _ = writeln!(output, "\n<{}>:", function);
+        }
+
+        let location = match line_table.location(address) {
+            Some(location) => format!("{}:{}", location.file, location.span.line),
+            None => String::new(),
+        };
+
+        let _ = writeln!(
+            output,
+            "{:04}  {:<14} {:<32} {}",
+            address,
+            mnemonic(operation),
+            operands(operation, address, byte_code),
+            location
+        );
+    }
+
+    let _ = writeln!(output, "\n== constants ==");
+
+    for (index, value) in byte_code.constants.iter().enumerate() {
+        let _ = writeln!(output, "#{:<4} {:?}", index, value);
+    }
+
+    let _ = writeln!(output, "\n== functions ==");
+
+    for function in functions {
+        let arguments = function
+            .arguments
+            .iter()
+            .map(name)
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        let _ = writeln!(
+            output,
+            "{:04}  {}({})",
+            function.address,
+            name(&function.name),
+            arguments
+        );
+    }
+
+    output
+}
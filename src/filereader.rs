@@ -9,85 +9,83 @@
 // Filereader - simple module for reading and formatting code
 
 use std::fs;
-use std::io::Read;
-
-fn error(message: String) {
-    eprintln!("{}", message);
-    std::process::exit(1);
-}
 
 pub fn remove_comments(text: String) -> String {
-    text.lines()
-        .map(|line| {
-            if let Some(index) = line.find("//") {
-                &line[..index]
-            } else {
-                line
+    // comments are removed until the end of line, but `//` inside string literals is kept
+
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    let mut is_string = false;
+    let mut is_comment = false;
+
+    while let Some(c) = chars.next() {
+        if is_comment {
+            if c == '\n' {
+                is_comment = false;
+                output.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            '"' => is_string = !is_string,
+            '\\' if is_string => {
+                // keeping escaped char as is, so `\"` doesn't end the string
+                output.push(c);
+
+                if let Some(escaped) = chars.next() {
+                    output.push(escaped);
+                }
+                continue;
             }
-        })
-        .collect::<Vec<&str>>()
-        .join("\n")
+            '/' if !is_string && chars.peek() == Some(&'/') => {
+                is_comment = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        output.push(c);
+    }
+
+    output
 }
 
-pub fn get_code(path_to_file: String) -> String {
+pub fn get_code(path_to_file: String) -> std::io::Result<String> {
     // reading code from source
-    let source_code = match fs::read_to_string(&path_to_file) {
-        Ok(f) => f,
-        Err(e) => {
-            error(format!("{} | Error while opening:\n{}", &path_to_file, e));
-            "\0".to_string()
-        }
-    };
+    let source_code = fs::read_to_string(path_to_file)?;
 
     // formatting code
 
-    let formatted_code = remove_comments(source_code)
+    Ok(remove_comments(source_code)
         // .replace("\n", "")
-        .replace("\r", "");
-
-    formatted_code
+        .replace("\r", ""))
 }
 
-pub fn search_import(path_to_file: String) -> String {
+pub fn search_import(path_to_file: String) -> Result<String, String> {
     // checking if input string is only filename
-    let current_dir = std::env::current_dir().expect("Failed to get current directory");
+    let current_dir = std::env::current_dir()
+        .map_err(|e| format!("Failed to get current directory: {}", e))?;
     let current_dir_file = current_dir.join(&path_to_file);
 
-    let exe_path = std::env::current_exe().expect("Failed to get executable directory");
-    let exe_dir = exe_path
-        .parent()
-        .expect("Failed to get executable directory");
-    let exe_path_file = exe_dir.join(&path_to_file);
+    let exe_path = std::env::current_exe()
+        .map_err(|e| format!("Failed to get executable directory: {}", e))?;
+    let exe_path_file = match exe_path.parent() {
+        Some(exe_dir) => exe_dir.join(&path_to_file),
+        None => return Err("Failed to get executable directory".to_string()),
+    };
 
-    match (current_dir_file.exists(), exe_path_file.exists()) {
-        (false, false) => return "FILE_NOT_FOUND_1_HIW_ERROR".to_string(),
-        (true, false) => return current_dir_file.to_str().unwrap().to_string(),
+    let found = match (current_dir_file.exists(), exe_path_file.exists()) {
+        (false, false) => return Err(format!("Import '{}' not found!", path_to_file)),
+        (true, false) => current_dir_file,
         // First priority to executable path modules
-        (false, true) => return exe_path_file.to_str().unwrap().to_string(),
-        (true, true) => return exe_path_file.to_str().unwrap().to_string(),
-    }
-}
-
-pub fn get_vm_code() -> String {
-    // checking vm at the compiler bin
-    let exe_dir = std::env::current_exe().unwrap();
-    let _dir = exe_dir.parent().unwrap();
-
-    let vm_file = _dir.join("vm.rs");
-
-    if vm_file.exists() {
-        let mut _file = std::fs::File::open(vm_file).unwrap();
-        let mut _source_code = String::new();
-
-        let _ = _file.read_to_string(&mut _source_code);
-
-        return _source_code;
-    } else {
-        let mut _file = std::fs::File::open("src/vm.rs").unwrap();
-        let mut _source_code = String::new();
-
-        let _ = _file.read_to_string(&mut _source_code);
+        (false, true) => exe_path_file,
+        (true, true) => exe_path_file,
+    };
 
-        return _source_code;
+    match found.to_str() {
+        Some(path) => Ok(path.to_string()),
+        None => Err(format!("Import '{}' has invalid path!", path_to_file)),
     }
 }
@@ -14,8 +14,5 @@ const APP_NAME: &str = env!("CARGO_PKG_NAME");
 use colored::Colorize;
 
 pub fn greeting() {
-    println!(
-        "{}",
-        format!("{}", format!("| {} | {}", APP_NAME, APP_VERSION).cyan())
-    )
+    println!("{}", format!("| {} | {}", APP_NAME, APP_VERSION).cyan())
 }
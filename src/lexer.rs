@@ -8,10 +8,10 @@
 
 // Lexer Analyzer - thing that gives me abstract data types (tokens) from just a string.
 
+use crate::diagnostic::{Diagnostic, Phase, Span};
 use crate::vm::Value;
-use colored::Colorize;
 #[allow(unused)]
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Token {
@@ -22,6 +22,8 @@ pub enum Token {
     // Boolean
     TRUE,
     FALSE,
+    // Nothing
+    NULL,
     // Operations
     PLUS,
     MINUS,
@@ -40,7 +42,6 @@ pub enum Token {
     LBRACK,
     RBRACK,
     // Signs
-    QUOTE,
     EXCLAM,
     QUESTM,
     DOT,
@@ -50,6 +51,13 @@ pub enum Token {
     // Comparsions
     LESS,
     BIGGER,
+    DOUBLE_EQUAL,
+    NOT_EQUAL,
+    LESS_EQUAL,
+    BIGGER_EQUAL,
+    // Logical
+    AND,
+    OR,
     // Functions and Constructions
     PRINT,
     INPUT,
@@ -58,9 +66,12 @@ pub enum Token {
     ELSE,
     WHILE,
     FOR,
+    BREAK,
+    CONTINUE,
 
     // Keywords
     DEFINE,
+    RETURN,
     USING,
     IN,
 
@@ -71,25 +82,46 @@ pub enum Token {
     EOF,
 }
 
+// Lex error - all wrong characters and literals found in the file
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rendered = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", rendered.join("\n"))
+    }
+}
+
+impl std::error::Error for LexError {}
+
 #[derive(Debug, Clone)]
 pub struct Lexer {
     pub source_code: String,
     pub filename: String,
 
     pub symbols: HashMap<char, Token>,
+    pub double_symbols: HashMap<[char; 2], Token>,
     pub words: HashMap<String, Token>,
-    pub errors: Vec<String>,
+    pub errors: Vec<Diagnostic>,
 
     pub input: Vec<char>,
     pub position: usize,
     pub current_line: usize,
+    pub current_column: usize,
     pub char: char,
 
     pub token: Option<Token>,
     pub value: Option<Value>,
-
-    pub is_string: bool,
-    pub space_before: bool,
+    pub span: Span,
 }
 
 impl Lexer {
@@ -104,7 +136,6 @@ impl Lexer {
             ('/', Token::DIVIDE),
             ('=', Token::EQUAL),
             (';', Token::SEMICOLON),
-            ('"', Token::QUOTE),
             ('!', Token::EXCLAM),
             ('?', Token::QUESTM),
             (':', Token::COLON),
@@ -118,20 +149,33 @@ impl Lexer {
             (']', Token::RBRACK),
         ]);
 
+        let double_symbols = HashMap::from([
+            (['=', '='], Token::DOUBLE_EQUAL),
+            (['!', '='], Token::NOT_EQUAL),
+            (['<', '='], Token::LESS_EQUAL),
+            (['>', '='], Token::BIGGER_EQUAL),
+            (['&', '&'], Token::AND),
+            (['|', '|'], Token::OR),
+        ]);
+
         let words = HashMap::from([
             ("print".to_string(), Token::PRINT),
             ("input".to_string(), Token::INPUT),
             //
             ("false".to_string(), Token::FALSE),
             ("true".to_string(), Token::TRUE),
+            ("null".to_string(), Token::NULL),
             //
             ("if".to_string(), Token::IF),
             ("else".to_string(), Token::ELSE),
             ("while".to_string(), Token::WHILE),
             ("for".to_string(), Token::FOR),
+            ("break".to_string(), Token::BREAK),
+            ("continue".to_string(), Token::CONTINUE),
             //
             ("using".to_string(), Token::USING),
             ("define".to_string(), Token::DEFINE),
+            ("return".to_string(), Token::RETURN),
             ("in".to_string(), Token::IN),
             //
             ("op!".to_string(), Token::OP),
@@ -139,6 +183,7 @@ impl Lexer {
 
         let mut lexer = Lexer {
             symbols,
+            double_symbols,
             words,
             errors: Vec::new(),
             input: input.chars().collect(),
@@ -146,11 +191,11 @@ impl Lexer {
             filename,
             position: 0,
             current_line: 1,
+            current_column: 0,
             char: ' ',
             token: None,
             value: None,
-            is_string: false,
-            space_before: false,
+            span: Span::default(),
         };
 
         lexer.getc();
@@ -158,25 +203,44 @@ impl Lexer {
     }
 
     fn error(&mut self, message: String) {
-        let current_line_source =
-            self.source_code.lines().collect::<Vec<&str>>()[self.current_line - 1];
+        let span = Span::new(self.current_line, self.current_column, self.current_column + 1);
 
-        let error_message = format!(
-            "{} {}\n{}\n{}\n {} {}",
-            "error:".red(),
-            message,
-            format!("    |- {}", self.filename).cyan(),
-            "    |".cyan(),
-            format!("{}  |", self.current_line).cyan(),
-            current_line_source,
-        );
+        self.error_at(span, message);
+        self.getc();
+    }
 
-        self.errors.push(error_message);
+    fn error_at(&mut self, span: Span, message: String) {
+        let diagnostic =
+            Diagnostic::error(Phase::Lexer, message).at(&self.filename, &self.source_code, span);
 
-        self.getc();
+        self.errors.push(diagnostic);
+    }
+
+    // scanning the whole file on a copy, so lexer itself stays at the beginning
+
+    pub fn check(&self) -> Result<(), LexError> {
+        let mut lexer = self.clone();
+
+        while lexer.token != Some(Token::EOF) {
+            lexer.next_token();
+        }
+
+        if lexer.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(LexError {
+                diagnostics: lexer.errors,
+            })
+        }
     }
 
     pub fn getc(&mut self) {
+        if self.char == '\n' {
+            self.current_column = 1;
+        } else {
+            self.current_column += 1;
+        }
+
         if self.position < self.input.len() {
             self.char = self.input[self.position];
             self.position += 1;
@@ -185,143 +249,224 @@ impl Lexer {
         }
     }
 
+    fn peekc(&self, offset: usize) -> char {
+        // `position` already points to the char after current
+        *self.input.get(self.position + offset).unwrap_or(&'\0')
+    }
+
+    fn number_literal(&mut self) -> String {
+        let mut literal = String::new();
+
+        while self.char.is_ascii_digit() {
+            literal.push(self.char);
+            self.getc();
+        }
+
+        // fraction part (dot without digits after it is a method call: `5.to_str()`)
+
+        if self.char == '.' && self.peekc(0).is_ascii_digit() {
+            literal.push(self.char);
+            self.getc();
+
+            while self.char.is_ascii_digit() {
+                literal.push(self.char);
+                self.getc();
+            }
+        }
+
+        // exponent part: 1e3, 2.5E-4
+
+        if self.char == 'e' || self.char == 'E' {
+            let exponent_digit = match self.peekc(0) {
+                '+' | '-' => self.peekc(1).is_ascii_digit(),
+                c => c.is_ascii_digit(),
+            };
+
+            if exponent_digit {
+                literal.push(self.char);
+                self.getc();
+
+                if self.char == '+' || self.char == '-' {
+                    literal.push(self.char);
+                    self.getc();
+                }
+
+                while self.char.is_ascii_digit() {
+                    literal.push(self.char);
+                    self.getc();
+                }
+            }
+        }
+
+        literal
+    }
+
+    fn string_literal(&mut self) -> Option<String> {
+        // skipping opening quote
+        let start_line = self.current_line;
+        let start_column = self.current_column;
+        self.getc();
+
+        let mut literal = String::new();
+
+        loop {
+            match self.char {
+                '"' => {
+                    self.getc();
+                    return Some(literal);
+                }
+                '\0' => {
+                    self.error_at(
+                        Span::new(start_line, start_column, start_column + 1),
+                        format!("Unterminated string literal started at line {}", start_line),
+                    );
+                    return None;
+                }
+                '\\' => {
+                    self.getc();
+
+                    let escaped = match self.char {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '"' => '"',
+                        '\\' => '\\',
+                        'u' => match self.unicode_escape() {
+                            Some(c) => c,
+                            None => continue,
+                        },
+                        '\0' => continue,
+                        c => {
+                            self.error(format!("Unknown escape sequence: \\{}", c));
+                            continue;
+                        }
+                    };
+
+                    literal.push(escaped);
+                    self.getc();
+                }
+                c => {
+                    if c == '\n' {
+                        self.current_line += 1;
+                    }
+
+                    literal.push(c);
+                    self.getc();
+                }
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Option<char> {
+        // \u{1F600} - current char is 'u'
+        self.getc();
+
+        if self.char != '{' {
+            self.error("Expected '{' after \\u".to_string());
+            return None;
+        }
+        self.getc();
+
+        let mut code = String::new();
+        while self.char.is_ascii_hexdigit() {
+            code.push(self.char);
+            self.getc();
+        }
+
+        if self.char != '}' {
+            self.error("Expected '}' to end unicode escape".to_string());
+            return None;
+        }
+
+        match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+            Some(c) => Some(c),
+            None => {
+                self.error(format!("Invalid unicode escape: \\u{{{}}}", code));
+                None
+            }
+        }
+    }
+
     pub fn next_token(&mut self) {
         (self.token, self.value) = (None, None);
 
         while self.token.is_none() {
+            let (start_line, start_column) = (self.current_line, self.current_column);
+
             match self.char {
                 '\0' => self.token = Some(Token::EOF),
                 '\n' => {
                     self.current_line += 1;
                     self.getc();
                 }
-                _ if self.char.is_whitespace() => {
-                    if self.is_string {
-                        self.space_before = true;
+                _ if self.char.is_whitespace() => self.getc(),
+                '"' => {
+                    if let Some(literal) = self.string_literal() {
+                        self.token = Some(Token::STR);
+                        self.value = Some(Value::STR(literal));
                     }
-
-                    self.getc();
                 }
-                '-' => {
+                _ if self.double_symbols.contains_key(&[self.char, self.peekc(0)]) => {
+                    self.token = Some(self.double_symbols[&[self.char, self.peekc(0)]]);
+                    self.getc();
                     self.getc();
-                    if self.char.is_digit(10) {
-                        let mut value = 0;
-                        while self.char.is_digit(10) {
-                            value = value * 10 + self.char.to_digit(10).unwrap() as i32;
-                            self.getc();
-                        }
-
-                        value = value * -1;
-
-                        match self.is_string {
-                            true => {
-                                self.token = Some(Token::STR);
-
-                                self.value = match self.space_before {
-                                    true => {
-                                        self.space_before = false;
-                                        Some(Value::STR(format!(" {}", value)))
-                                    }
-                                    false => Some(Value::STR(value.to_string())),
-                                }
-                            }
-                            false => {
-                                self.token = Some(Token::NUM);
-                                self.value = Some(Value::INT(value))
-                            }
-                        }
-                    } else {
-                        self.token = Some(Token::MINUS);
-                    }
                 }
                 _ if self.symbols.contains_key(&self.char) => {
-                    let matched_token = self.symbols.get(&self.char).unwrap().clone();
-
-                    if matched_token == Token::QUOTE {
-                        self.token = Some(matched_token);
-                        self.is_string = !self.is_string;
-                        self.getc();
-                    } else {
-                        match self.is_string {
-                            true => {
-                                self.token = Some(Token::STR);
-
-                                self.value = match self.space_before {
-                                    true => {
-                                        self.space_before = false;
-                                        Some(Value::STR(format!(" {}", self.char)))
-                                    }
-                                    false => Some(Value::STR(self.char.to_string())),
-                                }
-                            }
-                            false => self.token = Some(matched_token),
-                        }
-
-                        self.getc();
-                    }
+                    self.token = Some(*self.symbols.get(&self.char).unwrap());
+                    self.getc();
                 }
-                _ if self.char.is_digit(10) => {
-                    let mut value = 0;
-                    while self.char.is_digit(10) {
-                        value = value * 10 + self.char.to_digit(10).unwrap() as i32;
-                        self.getc();
-                    }
-
-                    match self.is_string {
-                        true => {
-                            self.token = Some(Token::STR);
-
-                            self.value = match self.space_before {
-                                true => {
-                                    self.space_before = false;
-                                    Some(Value::STR(format!(" {}", value)))
-                                }
-                                false => Some(Value::STR(value.to_string())),
-                            }
-                        }
-                        false => {
-                            self.token = Some(Token::NUM);
-                            self.value = Some(Value::INT(value))
-                        }
+                _ if self.char.is_ascii_digit() => {
+                    let value = self.number_literal();
+                    let is_float = value.contains(['.', 'e', 'E']);
+
+                    self.token = Some(Token::NUM);
+                    self.value = match is_float {
+                        true => value.parse::<f64>().ok().map(Value::FLOAT),
+                        false => value.parse::<i32>().ok().map(Value::INT),
+                    };
+
+                    if self.value.is_none() {
+                        self.value = Some(Value::INT(0));
+                        self.error_at(
+                            Span::new(start_line, start_column, self.current_column),
+                            format!("Number '{}' is too big", value),
+                        );
                     }
                 }
                 _ if self.char.is_alphabetic() => {
-                    let allowed_chars_in_id = ['!', '_', '-', '.'];
+                    let allowed_chars_in_id = ['!', '_'];
 
                     let mut id = String::new();
                     while self.char.is_alphanumeric() || allowed_chars_in_id.contains(&self.char) {
+                        // `a!=b` is comparison, not `a!` name
+
+                        if self.char == '!' && self.peekc(0) == '=' {
+                            break;
+                        }
+
                         id.push(self.char);
                         self.getc();
                     }
 
                     if self.words.contains_key(&id) {
-                        let matched_token = Some(self.words.get(&id).unwrap().clone());
-
-                        self.token = matched_token;
+                        self.token = Some(*self.words.get(&id).unwrap());
                     } else {
-                        match self.is_string {
-                            false => {
-                                self.token = Some(Token::ID);
-                                self.value = Some(Value::STR(id));
-                            }
-                            true => {
-                                self.token = Some(Token::STR);
-
-                                self.value = match self.space_before {
-                                    true => {
-                                        self.space_before = false;
-                                        Some(Value::STR(format!(" {}", id)))
-                                    }
-                                    false => Some(Value::STR(id)),
-                                }
-                            }
-                        }
+                        self.token = Some(Token::ID);
+                        self.value = Some(Value::STR(id));
                     }
                 }
                 _ => {
-                    let _ = self.error(format!("Undefined symbol: {}", self.char));
+                    self.error(format!("Undefined symbol: {}", self.char));
                 }
             }
+
+            // token span ends on the same line where it started
+
+            let end_column = match self.current_line == start_line {
+                true => self.current_column,
+                false => start_column + 1,
+            };
+            self.span = Span::new(start_line, start_column, end_column);
         }
     }
 }
@@ -0,0 +1,149 @@
+// hiw-lang compiler
+// https://github.com/mealet/hiw-lang
+// ----------------------------------------
+// Copyright ©️ 2024, mealet.
+// Project licensed under the BSD-3 License
+// that can be found in LICENSE file.
+// ----------------------------------------
+
+// Library - hiw for embedding into Rust programs. Compiles source to byte code, which runs on VM:
+//
+//     let byte_code = hiw::compile("main.hiw", "define twice(x) { return x * 2; };")?;
+//     let mut vm = hiw::VM::from(byte_code);
+//
+//     vm.run()?;
+//     let result = vm.call("twice", vec![hiw::Value::INT(21)])?;
+
+#![allow(
+    non_camel_case_types,
+    clippy::upper_case_acronyms,
+    clippy::needless_return
+)]
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod binary_compiler;
+pub mod bytecode;
+pub mod compiler;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod filereader;
+pub mod lexer;
+pub mod parser;
+pub mod vm;
+
+use std::fmt;
+
+pub use bytecode::FormatError;
+pub use compiler::{ByteCode, CompileError};
+pub use diagnostic::Diagnostic;
+pub use lexer::LexError;
+pub use parser::ParseError;
+pub use vm::{RuntimeError, Value, VM};
+
+// Error of any phase, so callers can use one type with `?`
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Lex(LexError),
+    Parse(ParseError),
+    Compile(CompileError),
+    Load(FormatError),
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Lex(error) => error.diagnostics.clone(),
+            Error::Parse(error) => error.diagnostics.clone(),
+            Error::Compile(error) => error.diagnostics.clone(),
+            Error::Load(error) => vec![*error.diagnostic.clone()],
+            Error::Runtime(error) => vec![*error.diagnostic.clone()],
+        }
+    }
+
+    // all diagnostics with colors for terminal
+
+    pub fn render(&self) -> String {
+        self.diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex(error) => write!(f, "{}", error),
+            Error::Parse(error) => write!(f, "{}", error),
+            Error::Compile(error) => write!(f, "{}", error),
+            Error::Load(error) => write!(f, "{}", error),
+            Error::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<LexError> for Error {
+    fn from(error: LexError) -> Self {
+        Error::Lex(error)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::Parse(error)
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(error: CompileError) -> Self {
+        Error::Compile(error)
+    }
+}
+
+impl From<FormatError> for Error {
+    fn from(error: FormatError) -> Self {
+        Error::Load(error)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Error::Runtime(error)
+    }
+}
+
+// Compiling source code, filename is used only in diagnostics
+
+pub fn compile(filename: &str, source: &str) -> Result<ByteCode, Error> {
+    compile_with_globals(filename, source, &[])
+}
+
+// Compiling source code which reads globals set by host with `VM::set_global`
+
+pub fn compile_with_globals(
+    filename: &str,
+    source: &str,
+    globals: &[&str],
+) -> Result<ByteCode, Error> {
+    let source = filereader::remove_comments(source.to_string()).replace('\r', "");
+
+    let lexer = lexer::Lexer::new(source.clone(), filename.to_string());
+    lexer.check()?;
+
+    let mut parser = parser::Parser::new(lexer);
+    let abstract_syntax_tree = parser.parse()?;
+
+    let mut compiler = compiler::Compiler::new(filename.to_string(), source);
+    compiler.declare_globals(globals)?;
+
+    let byte_code = compiler.compile_all(abstract_syntax_tree)?;
+
+    Ok(byte_code)
+}
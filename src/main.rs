@@ -6,105 +6,167 @@
 // that can be found in LICENSE file.
 // ----------------------------------------
 
-#[allow(unused, dead_code)]
-#[macro_use]
-extern crate lazy_static;
-
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 
+// Exit codes: errors in source code and errors while running it
+
+const EXIT_COMPILE_ERROR: i32 = 1;
+const EXIT_RUNTIME_ERROR: i32 = 2;
+
 use colored::Colorize;
+use hiw::{binary_compiler, bytecode, disassembler, filereader};
 
-mod binary_compiler;
-mod compiler;
-mod filereader;
 mod greeting;
-mod lexer;
-mod parser;
-mod vm;
+mod repl;
 
-fn main() {
-    // Option Variables
+fn exit_with(error: hiw::Error) -> ! {
+    eprintln!("{}", error.render());
 
-    let mut compile_mode = false;
+    match error {
+        hiw::Error::Runtime(_) => std::process::exit(EXIT_RUNTIME_ERROR),
+        _ => std::process::exit(EXIT_COMPILE_ERROR),
+    }
+}
 
-    // Greeting user
+// reading compiled byte code, source files are compiled first
+
+fn load(filepath: &std::path::Path) -> hiw::ByteCode {
+    if filepath.extension().and_then(|ext| ext.to_str()) == Some(bytecode::EXTENSION) {
+        let bytes = match std::fs::read(filepath) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("{} | Error while opening:\n{}", filepath.display(), err);
+                std::process::exit(EXIT_COMPILE_ERROR);
+            }
+        };
+
+        return match bytecode::decode(&bytes) {
+            Ok(byte_code) => byte_code,
+            Err(err) => exit_with(err.into()),
+        };
+    }
 
-    greeting::greeting();
+    let filename = filepath.file_name().unwrap();
+    let input = match filereader::get_code(filepath.to_string_lossy().to_string()) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("{} | Error while opening:\n{}", filepath.display(), err);
+            std::process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
 
-    // Getting args
-    let args: Vec<String> = std::env::args().collect();
+    let byte_code = match hiw::compile(filename.to_str().unwrap(), &input) {
+        Ok(byte_code) => byte_code,
+        Err(err) => exit_with(err),
+    };
 
-    if args.clone().len() < 2 {
-        eprintln!(
-            "| Usage for compiling and running: {}\n|-- Example: {}\n|\n| Usage for compiling to binary file: {}\n|-- Example: {}",
-            format!("{} [file]", APP_NAME).red(), format!("{} example.hiw", APP_NAME).red(), format!("{} [file] [output]", APP_NAME).red(), format!("{} example.hiw output", APP_NAME).red()
-        );
-        std::process::exit(1);
-    } else if args.clone().len() > 2 {
-        compile_mode = true;
+    for warning in &byte_code.warnings {
+        eprintln!("{}", warning.render());
     }
 
-    let filepath = std::path::Path::new(&args[1]);
-    let filename = filepath.file_name().unwrap();
+    byte_code
+}
 
-    // Creating Lexer Analyzer
+fn run(filepath: &std::path::Path) {
+    let mut vm = hiw::VM::from(load(filepath));
 
-    let input = filereader::get_code(filepath.to_str().unwrap().to_string());
-    let lexer = lexer::Lexer::new(input, filename.to_str().unwrap().to_string());
+    if let Err(err) = vm.run() {
+        exit_with(err.into());
+    }
+}
 
-    // Checking lexical analyzer errors
+fn build(filepath: &std::path::Path, output: Option<String>, flags: &[String]) {
+    let byte_code = load(filepath);
+    let stem = filepath.file_stem().unwrap().to_str().unwrap().to_string();
 
-    let mut lexer_clone = lexer.clone();
-    while lexer_clone.token != Some(lexer::Token::EOF) {
-        lexer_clone.next_token();
-    }
+    if flags.iter().any(|flag| flag == "--bytecode") {
+        let output = output.unwrap_or(format!("{}.{}", stem, bytecode::EXTENSION));
+        let with_line_table = !flags.iter().any(|flag| flag == "--strip");
 
-    if lexer_clone.errors.len() > 0 {
-        for err in lexer_clone.errors {
-            eprintln!("{}", err);
+        if let Err(err) = std::fs::write(&output, bytecode::encode(&byte_code, with_line_table)) {
+            eprintln!("{} | Error while writing:\n{}", output, err);
+            std::process::exit(EXIT_COMPILE_ERROR);
         }
-        std::process::exit(1);
+
+        println!("{} {}", "| Bytecode written to".cyan(), output.cyan());
+        return;
     }
 
-    // Parsing Lexer Tokens
+    // Wrapping byte code into copy of hiw
 
-    let mut parser = parser::Parser::new(lexer);
-    let abstract_syntax_tree = parser.parse();
+    let with_line_table = !flags.iter().any(|flag| flag == "--strip");
 
-    // Checking parser errors
+    let compile_container =
+        binary_compiler::Container::new(output.unwrap_or(stem), byte_code, with_line_table);
 
-    if parser.errors.len() > 0 {
-        for err in parser.errors {
-            eprintln!("{}", err);
-            std::process::exit(1);
-        }
+    if let Err(err) = compile_container.compile() {
+        eprintln!(
+            "{} An error occured while compiling: {}",
+            "[BinaryCompiler]:".red(),
+            err
+        );
+        std::process::exit(EXIT_COMPILE_ERROR);
     }
+}
 
-    // Compiling Tree to byte code
+fn usage() -> ! {
+    eprintln!(
+        "| Usage for compiling and running: {}\n|-- Example: {}\n|\n| Usage for compiling to binary file: {} [--strip]\n|-- Example: {}\n|\n| Usage for compiling to bytecode: {}\n|-- Example: {}\n|\n| Usage for printing bytecode: {}\n|\n| Usage for interactive mode: {}",
+        format!("{} run [file]", APP_NAME).red(), format!("{} run example.hiw", APP_NAME).red(), format!("{} build [file] [output]", APP_NAME).red(), format!("{} build example.hiw output", APP_NAME).red(), format!("{} build [file] [output] --bytecode [--strip]", APP_NAME).red(), format!("{} build example.hiw --bytecode && {} run example.hiwc", APP_NAME, APP_NAME).red(), format!("{} disasm [file]", APP_NAME).red(), format!("{} repl", APP_NAME).red()
+    );
+    std::process::exit(1);
+}
 
-    let mut compiler = compiler::Compiler::new();
-    let byte_code = compiler.compile_all(abstract_syntax_tree);
+fn main() {
+    // Running program if this executable was built by `hiw build`
 
-    // Creating VM
+    if let Some(byte_code) = binary_compiler::embedded() {
+        let byte_code = match byte_code {
+            Ok(byte_code) => byte_code,
+            Err(err) => exit_with(err.into()),
+        };
 
-    let mut vm = vm::VM::new(byte_code.program);
+        if let Err(err) = hiw::VM::from(byte_code).run() {
+            exit_with(err.into());
+        }
+
+        return;
+    }
 
-    // Checking compile mode
+    // Greeting user
 
-    if compile_mode {
-        // Compiling VM with commands
+    greeting::greeting();
 
-        let output_filename = args[2].clone();
+    // Getting args
 
-        let compile_container = binary_compiler::Container::new(output_filename, vm);
-        let _ = compile_container.compile();
-    } else {
-        // Running VM
+    let args: Vec<String> = std::env::args().collect();
 
-        let _ = vm.run();
+    let (flags, args): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| arg.starts_with("--"));
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        None => usage(),
+        Some("repl") => repl::run(),
+        Some("run") => match args.get(2) {
+            Some(file) => run(std::path::Path::new(file)),
+            None => usage(),
+        },
+        Some("disasm") => match args.get(2) {
+            Some(file) => print!(
+                "{}",
+                disassembler::disassemble(&load(std::path::Path::new(file)))
+            ),
+            None => usage(),
+        },
+        Some("build") => match args.get(2) {
+            Some(file) => build(std::path::Path::new(file), args.get(3).cloned(), &flags),
+            None => usage(),
+        },
+
+        // short forms: `hiw [file]` and `hiw [file] [output]`
+        Some(file) => match args.get(2) {
+            Some(output) => build(std::path::Path::new(file), Some(output.clone()), &flags),
+            None => run(std::path::Path::new(file)),
+        },
     }
 }
-
-// TODO: Add functions:
-//              <ARRAY>.push(arg)
-//              <ARRAY>.join(<STR>)
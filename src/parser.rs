@@ -8,9 +8,9 @@
 
 // Parser - hardest module in compiler (ig). It creates Binary Tree with abstract image of code
 
+use crate::diagnostic::{Diagnostic, Phase, Span};
 use crate::vm::Value;
-#[allow(dead_code, unused)]
-use colored::Colorize;
+use std::fmt;
 
 type LEXER = crate::lexer::Lexer;
 type VALUE = crate::vm::Value;
@@ -18,14 +18,16 @@ type OPTION = Option<Box<Node>>;
 
 use crate::lexer::Token;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Kind {
     // Types
     VAR,
     CONST,
     STRING,
     BOOL,
+    NULL,
     ARRAY,
+    MAP,
     EMPTY,
     // Operations
     ADD,
@@ -37,6 +39,13 @@ pub enum Kind {
     LT,
     BT,
     EQ,
+    NE,
+    LE,
+    BE,
+    // Logical
+    AND,
+    OR,
+    NOT,
     // Functions and Constructions
     PRINT,
     INPUT,
@@ -45,9 +54,13 @@ pub enum Kind {
     IF_ELSE,
     WHILE,
     FOR,
+    BREAK,
+    CONTINUE,
 
     FUNCTION_DEFINE,
     FUNCTION_CALL,
+    LAMBDA,
+    INDIRECT_CALL,
 
     FILE_IMPORT,
 
@@ -63,13 +76,14 @@ pub enum Kind {
     EXPR,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     pub kind: Kind,
     pub value: Option<VALUE>,
     pub op1: OPTION,
     pub op2: OPTION,
     pub op3: OPTION,
+    pub span: Span,
 }
 
 impl Node {
@@ -80,411 +94,478 @@ impl Node {
             op1,
             op2,
             op3,
+            span: Span::default(),
         }
     }
+
+    fn at(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
 }
 
 pub struct Parser {
     lexer: LEXER,
-    pub errors: Vec<String>,
+    pub errors: Vec<Diagnostic>,
+    aborted: bool,
+    loops: usize,
+}
+
+// Parse error - all syntax errors found in the file
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rendered = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", rendered.join("\n"))
+    }
 }
 
+impl std::error::Error for ParseError {}
+
 impl Parser {
     pub fn new(lexer: LEXER) -> Self {
         Parser {
             lexer,
             errors: Vec::new(),
+            aborted: false,
+            loops: 0,
         }
     }
 
+    fn diagnostic(&self, message: &str) -> Diagnostic {
+        Diagnostic::error(Phase::Parser, message).at(
+            &self.lexer.filename,
+            &self.lexer.source_code,
+            self.lexer.span,
+        )
+    }
+
     fn error(&mut self, message: &str) {
-        let current_line_source =
-            self.lexer.source_code.lines().collect::<Vec<&str>>()[self.lexer.current_line - 1];
-
-        let error_message = format!(
-            "{} {}\n{}\n{}\n {} {}",
-            "[ParserError]:".red(),
-            message,
-            format!("    |- {}", self.lexer.filename).cyan(),
-            "    |".cyan(),
-            format!("{}  |", self.lexer.current_line).cyan(),
-            current_line_source,
-        );
+        // after critical error parser only runs to the end of file, so errors would be noise
 
-        self.errors.push(error_message);
+        if !self.aborted {
+            let diagnostic = self.diagnostic(message);
+            self.errors.push(diagnostic);
+        }
 
         self.lexer.next_token();
     }
 
     fn critical_error(&mut self, message: &str) {
-        let current_line_source =
-            self.lexer.source_code.lines().collect::<Vec<&str>>()[self.lexer.current_line - 1];
-
-        let error_message = format!(
-            "{} {}\n{}\n{}\n {} {}",
-            "[CriticalParserError]:".red(),
-            message,
-            format!("    |- {}", self.lexer.filename).cyan(),
-            "    |".cyan(),
-            format!("{}  |", self.lexer.current_line).cyan(),
-            current_line_source,
-        );
+        // parser cannot continue, callers stop their loops right after this
 
-        eprintln!("{}", error_message);
-        std::process::exit(1);
-    }
+        if !self.aborted {
+            let diagnostic = self.diagnostic(message);
+            self.errors.push(diagnostic);
+        }
 
-    fn term(&mut self) -> Node {
-        let token = self.lexer.token.clone().unwrap();
+        self.aborted = true;
+    }
 
-        match token {
-            Token::ID => {
-                let id_name = self.lexer.value.clone().unwrap();
+    fn expect(&mut self, token: Token, message: &str) {
+        if self.lexer.token == Some(token) {
+            self.lexer.next_token();
+        } else {
+            self.error(message);
+        }
+    }
 
-                let mut node = Node::new(Kind::VAR, Some(id_name.clone()), None, None, None);
-                self.lexer.next_token();
+    fn postfix(&mut self, mut node: Node) -> Node {
+        loop {
+            match self.lexer.token {
+                Some(Token::LBRACK) => {
+                    let span = self.lexer.span;
+                    self.lexer.next_token();
 
-                match self.lexer.token {
-                    Some(Token::LBRACK) => {
-                        self.lexer.next_token();
+                    node = Node::new(
+                        Kind::SLICE,
+                        None,
+                        Some(Box::new(node)),
+                        Some(Box::new(self.expression())),
+                        None,
+                    )
+                    .at(span);
 
-                        node = Node::new(
-                            Kind::SLICE,
-                            None,
-                            Some(Box::new(node.clone())),
-                            Some(Box::new(self.expression())),
-                            None,
-                        );
+                    self.expect(Token::RBRACK, "Expected ']' after slice index");
+                }
+                Some(Token::DOT) => {
+                    // Going to next token which have function name
+                    self.lexer.next_token();
 
-                        self.lexer.next_token()
+                    if self.lexer.token != Some(Token::ID) {
+                        self.error("Expected function name after dot");
+                        return node;
                     }
-                    Some(Token::LPAR) => {
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(id_name),
-                            Some(Box::new(self.paren_arguments())),
-                            None,
-                            None,
-                        );
+
+                    node = Node::new(
+                        Kind::FUNCTION_CALL,
+                        self.lexer.value.clone(),
+                        Some(Box::new(node)),
+                        None,
+                        None,
+                    )
+                    .at(self.lexer.span);
+
+                    // Searching for '(' for function call
+
+                    self.lexer.next_token();
+
+                    if self.lexer.token != Some(Token::LPAR) {
+                        self.error("Expected '(' for function call");
+                        return node;
                     }
-                    Some(Token::DOT) => {
-                        // Going to next token which have function name
-                        self.lexer.next_token();
 
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(self.lexer.value.clone().unwrap_or_else(|| {
-                                self.error("Unexpected dot after ID");
-                                Value::INT(0)
-                            })),
-                            Some(Box::new(node.clone())),
-                            None,
-                            None,
-                        );
+                    // Parsing other arguments
 
-                        // Searching for '(' for function call
+                    node.op2 = Some(Box::new(self.paren_arguments()));
+                }
+                Some(Token::LPAR) => {
+                    // calling value of expression: `make_adder(1)(2)`, `handlers[0](x)`
 
-                        self.lexer.next_token();
+                    let span = self.lexer.span;
 
-                        if self.lexer.token != Some(Token::LPAR) {
-                            self.error("Expected '(' for function call");
-                        }
+                    node = Node::new(
+                        Kind::INDIRECT_CALL,
+                        None,
+                        Some(Box::new(node)),
+                        Some(Box::new(self.paren_arguments())),
+                        None,
+                    )
+                    .at(span);
+                }
+                _ => return node,
+            }
+        }
+    }
 
-                        // Parsing other arguments
+    fn term(&mut self) -> Node {
+        let token = self.lexer.token.unwrap();
+        let span = self.lexer.span;
 
-                        node.op2 = Some(Box::new(self.paren_arguments()));
-                    }
-                    _ => {}
-                };
+        let node = match token {
+            Token::ID => {
+                let id_name = self.lexer.value.clone().unwrap();
+                self.lexer.next_token();
 
-                return node;
+                if self.lexer.token == Some(Token::LPAR) {
+                    Node::new(
+                        Kind::FUNCTION_CALL,
+                        Some(id_name),
+                        Some(Box::new(self.paren_arguments())),
+                        None,
+                        None,
+                    )
+                } else {
+                    Node::new(Kind::VAR, Some(id_name), None, None, None)
+                }
             }
             Token::NUM => {
-                let mut node = Node::new(
-                    Kind::CONST,
-                    Some(self.lexer.value.clone().unwrap()),
+                let node = Node::new(Kind::CONST, self.lexer.value.clone(), None, None, None);
+                self.lexer.next_token();
+
+                node
+            }
+            Token::STR => {
+                let node = Node::new(Kind::STRING, self.lexer.value.clone(), None, None, None);
+                self.lexer.next_token();
+
+                node
+            }
+            Token::TRUE | Token::FALSE => {
+                let node = Node::new(
+                    Kind::BOOL,
+                    Some(Value::BOOL(token == Token::TRUE)),
                     None,
                     None,
                     None,
                 );
                 self.lexer.next_token();
 
-                match self.lexer.token {
-                    Some(Token::LBRACK) => {
-                        self.lexer.next_token();
-
-                        node = Node::new(
-                            Kind::SLICE,
-                            None,
-                            Some(Box::new(node.clone())),
-                            Some(Box::new(self.expression())),
-                            None,
-                        );
+                node
+            }
+            Token::NULL => {
+                let node = Node::new(Kind::NULL, Some(Value::NULL), None, None, None);
+                self.lexer.next_token();
 
-                        self.lexer.next_token();
-                    }
-                    Some(Token::DOT) => {
-                        // Going to next token which have function name
-                        self.lexer.next_token();
+                node
+            }
+            Token::LBRACK => {
+                self.lexer.next_token();
 
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(self.lexer.value.clone().unwrap_or_else(|| {
-                                self.error("Unexpected dot after ID");
-                                Value::INT(0)
-                            })),
-                            Some(Box::new(node.clone())),
-                            None,
-                            None,
-                        );
+                let mut temp_node = Node::new(Kind::EMPTY, None, None, None, None);
 
-                        // Searching for '(' for function call
+                while self.lexer.token != Some(Token::RBRACK) {
+                    if self.lexer.token == Some(Token::EOF) {
+                        self.critical_error("Expected ']' to end array!");
+                        break;
+                    }
 
-                        self.lexer.next_token();
+                    temp_node = Node::new(
+                        Kind::BRACK_ENUM,
+                        None,
+                        Some(Box::new(temp_node)),
+                        Some(Box::new(self.expression())),
+                        None,
+                    );
 
-                        if self.lexer.token != Some(Token::LPAR) {
-                            self.error("Expected '(' for function call");
+                    match self.lexer.token {
+                        Some(Token::COMMA) => self.lexer.next_token(),
+                        Some(Token::RBRACK) => {}
+                        _ => {
+                            self.error("Expected ',' or ']' in array");
+                            break;
                         }
-
-                        // Parsing other arguments
-
-                        node.op2 = Some(Box::new(self.paren_arguments()));
                     }
-                    _ => {}
                 }
 
-                return node;
+                self.lexer.next_token();
+
+                Node::new(Kind::ARRAY, None, Some(Box::new(temp_node)), None, None)
             }
-            Token::STR => {
-                let mut ident = String::new();
-                while self.lexer.token.unwrap() == Token::STR {
-                    if let Value::STR(str_val) = self.lexer.value.clone().unwrap() {
-                        ident.push_str(format!("{}", str_val).as_str());
-                    }
-                    self.lexer.next_token();
-                }
+            Token::LBRA => {
+                // map literal keeps keys and values one after another: {k1: v1, k2: v2}
 
-                let mut node = Node::new(Kind::STRING, Some(Value::STR(ident)), None, None, None);
+                self.lexer.next_token();
 
-                let mut lexer_clone = self.lexer.clone();
-                lexer_clone.next_token();
+                let mut temp_node = Node::new(Kind::EMPTY, None, None, None, None);
 
-                match lexer_clone.token {
-                    Some(Token::DOT) => {
-                        self.lexer.next_token();
+                while self.lexer.token != Some(Token::RBRA) {
+                    if self.lexer.token == Some(Token::EOF) {
+                        self.critical_error("Expected '}' to end map!");
+                        break;
+                    }
 
-                        // Going to next token which have function name
-                        self.lexer.next_token();
+                    let key = self.expression();
 
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(self.lexer.value.clone().unwrap_or_else(|| {
-                                self.error("Unexpected dot after ID");
-                                Value::INT(0)
-                            })),
-                            Some(Box::new(node.clone())),
+                    if self.lexer.token != Some(Token::COLON) {
+                        self.error("Expected ':' after map key");
+                        break;
+                    }
+
+                    self.lexer.next_token();
+
+                    for item in [key, self.expression()] {
+                        temp_node = Node::new(
+                            Kind::BRACK_ENUM,
                             None,
+                            Some(Box::new(temp_node)),
+                            Some(Box::new(item)),
                             None,
                         );
+                    }
 
-                        // Searching for '(' for function call
-
-                        self.lexer.next_token();
-
-                        if self.lexer.token != Some(Token::LPAR) {
-                            self.error("Expected '(' for function call");
+                    match self.lexer.token {
+                        Some(Token::COMMA) => self.lexer.next_token(),
+                        Some(Token::RBRA) => {}
+                        _ => {
+                            self.error("Expected ',' or '}' in map");
+                            break;
                         }
-
-                        // Parsing other arguments
-
-                        node.op2 = Some(Box::new(self.paren_arguments()));
                     }
-                    _ => {}
-                };
+                }
+
+                self.lexer.next_token();
 
-                return node;
+                Node::new(Kind::MAP, None, Some(Box::new(temp_node)), None, None)
             }
-            Token::TRUE => {
-                let mut node = Node::new(Kind::BOOL, Some(Value::BOOL(true)), None, None, None);
+            Token::DEFINE => {
+                // anonymous function: define(x) { return x * 2; }
 
                 self.lexer.next_token();
 
-                match self.lexer.token {
-                    Some(Token::DOT) => {
-                        // Going to next token which have function name
-                        self.lexer.next_token();
+                if self.lexer.token != Some(Token::LPAR) {
+                    self.error("Expected '(' after 'define' in expression");
+                    return Node::new(Kind::EMPTY, None, None, None, None);
+                }
 
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(self.lexer.value.clone().unwrap_or_else(|| {
-                                self.error("Unexpected dot after ID");
-                                Value::INT(0)
-                            })),
-                            Some(Box::new(node.clone())),
-                            None,
-                            None,
-                        );
+                let arguments = self.paren_arguments();
 
-                        // Searching for '(' for function call
+                if self.lexer.token != Some(Token::LBRA) {
+                    self.error("Expected '{' after function arguments");
+                    return Node::new(Kind::EMPTY, None, None, None, None);
+                }
 
-                        self.lexer.next_token();
+                let loops = std::mem::replace(&mut self.loops, 0);
+                let body = self.block();
+                self.loops = loops;
 
-                        if self.lexer.token != Some(Token::LPAR) {
-                            self.error("Expected '(' for function call");
-                        }
+                Node::new(
+                    Kind::LAMBDA,
+                    None,
+                    Some(Box::new(arguments)),
+                    Some(Box::new(body)),
+                    None,
+                )
+            }
+            Token::INPUT => {
+                self.lexer.next_token();
+
+                Node::new(
+                    Kind::INPUT,
+                    None,
+                    Some(Box::new(self.paren_expression())),
+                    None,
+                    None,
+                )
+            }
+            Token::LPAR => {
+                self.lexer.next_token();
 
-                        // Parsing other arguments
+                let node = self.expression();
 
-                        node.op2 = Some(Box::new(self.paren_arguments()));
-                    }
-                    _ => {}
-                }
+                self.expect(Token::RPAR, "Expected ')' to end paren block!");
 
-                return node;
+                node
+            }
+            Token::EOF => {
+                self.critical_error("Parser got End Of File trying to parse expression!");
+                Node::new(Kind::EMPTY, None, None, None, None)
+            }
+            _ => {
+                self.error(format!("Unexpected token {:?} in expression", token).as_str());
+                Node::new(Kind::EMPTY, None, None, None, None)
             }
-            Token::FALSE => {
-                let mut node = Node::new(Kind::BOOL, Some(Value::BOOL(false)), None, None, None);
+        };
 
-                self.lexer.next_token();
+        // grouped expressions keep span of their inner node
 
-                match self.lexer.token {
-                    Some(Token::DOT) => {
-                        // Going to next token which have function name
-                        self.lexer.next_token();
+        let node = if token == Token::LPAR { node } else { node.at(span) };
 
-                        node = Node::new(
-                            Kind::FUNCTION_CALL,
-                            Some(self.lexer.value.clone().unwrap_or_else(|| {
-                                self.error("Unexpected dot after ID");
-                                Value::INT(0)
-                            })),
-                            Some(Box::new(node.clone())),
-                            None,
-                            None,
-                        );
+        self.postfix(node)
+    }
 
-                        // Searching for '(' for function call
+    fn unary(&mut self) -> Node {
+        if self.lexer.token == Some(Token::EXCLAM) {
+            let span = self.lexer.span;
+            self.lexer.next_token();
 
-                        self.lexer.next_token();
+            let operand = self.unary();
 
-                        if self.lexer.token != Some(Token::LPAR) {
-                            self.error("Expected '(' for function call");
-                        }
+            return Node::new(Kind::NOT, None, Some(Box::new(operand)), None, None).at(span);
+        }
 
-                        // Parsing other arguments
+        if self.lexer.token == Some(Token::MINUS) {
+            let span = self.lexer.span;
+            self.lexer.next_token();
 
-                        node.op2 = Some(Box::new(self.paren_arguments()));
-                    }
-                    _ => {}
-                };
+            let operand = self.unary();
 
-                return node;
-            }
-            Token::COMMA => {
-                self.lexer.next_token();
-                return self.expression();
-            }
-            _ => return self.paren_expression(),
-        }
-    }
+            // negative numbers are folded to constants, other values are substracted from zero
 
-    fn summa(&mut self) -> Node {
-        let mut node = self.term();
-        let mut kind = Kind::EMPTY;
-
-        while self.lexer.token.clone().unwrap() == Token::PLUS
-            || self.lexer.token.clone().unwrap() == Token::MINUS
-            || self.lexer.token.clone().unwrap() == Token::MULTIPLY
-            || self.lexer.token.clone().unwrap() == Token::DIVIDE
-        {
-            match self.lexer.token.clone().unwrap() {
-                Token::PLUS => kind = Kind::ADD,
-                Token::MINUS => kind = Kind::SUB,
-                Token::MULTIPLY => kind = Kind::MULT,
-                Token::DIVIDE => kind = Kind::DIV,
+            match (&operand.kind, &operand.value) {
+                (Kind::CONST, Some(Value::INT(value))) => {
+                    return Node::new(Kind::CONST, Some(Value::INT(-value)), None, None, None)
+                        .at(span);
+                }
+                (Kind::CONST, Some(Value::FLOAT(value))) => {
+                    return Node::new(Kind::CONST, Some(Value::FLOAT(-value)), None, None, None)
+                        .at(span);
+                }
                 _ => {}
             }
 
-            self.lexer.next_token();
-            node = Node::new(
-                kind.clone(),
+            return Node::new(
+                Kind::SUB,
                 None,
-                Some(Box::new(node.clone())),
-                Some(Box::new(self.term())),
+                Some(Box::new(Node::new(
+                    Kind::CONST,
+                    Some(Value::INT(0)),
+                    None,
+                    None,
+                    None,
+                ))),
+                Some(Box::new(operand)),
                 None,
-            );
+            )
+            .at(span);
         }
 
-        return node;
+        self.term()
     }
 
-    fn test(&mut self) -> Node {
-        let mut node = self.summa();
+    // Binary operators with their precedence (bigger binds tighter):
+    //  1 | ||
+    //  2 | &&
+    //  3 | ==  !=
+    //  4 | <  >  <=  >=
+    //  5 | +  -
+    //  6 | *  /
+
+    fn binary_operator(&self) -> Option<(Kind, u8)> {
+        match self.lexer.token? {
+            Token::OR => Some((Kind::OR, 1)),
+            Token::AND => Some((Kind::AND, 2)),
+            Token::DOUBLE_EQUAL => Some((Kind::EQ, 3)),
+            Token::NOT_EQUAL => Some((Kind::NE, 3)),
+            Token::LESS => Some((Kind::LT, 4)),
+            Token::BIGGER => Some((Kind::BT, 4)),
+            Token::LESS_EQUAL => Some((Kind::LE, 4)),
+            Token::BIGGER_EQUAL => Some((Kind::BE, 4)),
+            Token::PLUS => Some((Kind::ADD, 5)),
+            Token::MINUS => Some((Kind::SUB, 5)),
+            Token::MULTIPLY => Some((Kind::MULT, 6)),
+            Token::DIVIDE => Some((Kind::DIV, 6)),
+            _ => None,
+        }
+    }
 
-        match self.lexer.token.unwrap() {
-            Token::LESS => {
-                self.lexer.next_token();
-                node = Node::new(
-                    Kind::LT,
-                    None,
-                    Some(Box::new(node.clone())),
-                    Some(Box::new(self.summa())),
-                    None,
-                );
-            }
-            Token::BIGGER => {
-                self.lexer.next_token();
-                node = Node::new(
-                    Kind::BT,
-                    None,
-                    Some(Box::new(node.clone())),
-                    Some(Box::new(self.summa())),
-                    None,
-                );
+    fn binary(&mut self, min_precedence: u8) -> Node {
+        let mut node = self.unary();
+
+        while let Some((kind, precedence)) = self.binary_operator() {
+            if precedence < min_precedence {
+                break;
             }
-            Token::EQUAL => {
-                let mut lexer_copy = self.lexer.clone();
-                lexer_copy.next_token();
 
-                if lexer_copy.token == Some(Token::EQUAL) {
-                    self.lexer.next_token();
-                    self.lexer.next_token();
+            let span = self.lexer.span;
 
-                    node = Node::new(
-                        Kind::EQ,
-                        None,
-                        Some(Box::new(node.clone())),
-                        Some(Box::new(self.summa())),
-                        None,
-                    );
-                }
-            }
-            _ => {}
+            self.lexer.next_token();
+
+            // all binary operators are left associative
+
+            let right = self.binary(precedence + 1);
+
+            node = Node::new(
+                kind,
+                None,
+                Some(Box::new(node)),
+                Some(Box::new(right)),
+                None,
+            )
+            .at(span);
         }
 
-        return node;
+        node
     }
 
     fn paren_expression(&mut self) -> Node {
+        if self.lexer.token != Some(Token::LPAR) {
+            self.error("Expected '(' to start paren block!");
+            return Node::new(Kind::EMPTY, None, None, None, None);
+        }
+
         self.lexer.next_token();
 
-        let mut node = Node::new(Kind::EMPTY, None, None, None, None);
+        if self.lexer.token == Some(Token::RPAR) {
+            self.lexer.next_token();
+            return Node::new(Kind::EMPTY, None, None, None, None);
+        }
 
-        match self.lexer.token {
-            Some(Token::RPAR) => {
-                node = Node::new(Kind::EMPTY, None, None, None, None);
-                self.lexer.next_token()
-            }
-            Some(Token::EOF) => {
-                self.error("Expected ')' to end paren block!");
-            }
-            _ => {
-                node = self.expression();
-                self.lexer.next_token();
-            }
-        };
+        let node = self.expression();
 
-        return node;
+        self.expect(Token::RPAR, "Expected ')' to end paren block!");
+
+        node
     }
 
     fn paren_arguments(&mut self) -> Node {
@@ -492,25 +573,15 @@ impl Parser {
 
         let mut node = Node::new(Kind::EMPTY, None, None, None, None);
 
-        if self.lexer.token == Some(Token::RPAR) {
-            self.lexer.next_token();
-            return node;
-        }
-
         while self.lexer.token != Some(Token::RPAR) {
-            if self.lexer.token == Some(Token::COMMA) {
-                self.lexer.next_token();
-            }
-
             match self.lexer.token {
-                Some(Token::COMMA) => self.lexer.next_token(),
                 Some(Token::EOF) => {
-                    self.critical_error("Parser got End Of File trying to parse arguments!")
+                    self.critical_error("Parser got End Of File trying to parse arguments!");
+                    return node;
                 }
                 Some(Token::SEMICOLON) => {
                     self.error("Parser cannot get data in '()'");
-                    self.lexer.next_token();
-                    break;
+                    return node;
                 }
                 _ => {}
             }
@@ -518,47 +589,141 @@ impl Parser {
             node = Node::new(
                 Kind::ARGS_ENUM,
                 None,
-                Some(Box::new(node.clone())),
+                Some(Box::new(node)),
                 Some(Box::new(self.expression())),
                 None,
             );
+
+            match self.lexer.token {
+                Some(Token::COMMA) => self.lexer.next_token(),
+                Some(Token::RPAR) => {}
+                _ => {
+                    self.error("Expected ',' or ')' in arguments");
+                    return node;
+                }
+            }
         }
 
         self.lexer.next_token();
 
-        return node;
+        node
     }
 
-    fn expression(&mut self) -> Node {
-        let token = self.lexer.token.clone().unwrap();
+    fn macro_arguments(&mut self) -> Node {
+        // op! arguments are separated with spaces: op!(FETCH a TYPE)
 
-        match token {
-            Token::LBRACK | Token::INPUT => {
-                return self.statement();
-            }
-            Token::ID => {
-                let mut node = self.test();
-                if node.kind == Kind::VAR && self.lexer.token.clone().unwrap() == Token::EQUAL {
+        self.lexer.next_token();
+
+        let mut node = Node::new(Kind::EMPTY, None, None, None, None);
+
+        while self.lexer.token != Some(Token::RPAR) {
+            match self.lexer.token {
+                Some(Token::EOF) => {
+                    self.critical_error("Parser got End Of File trying to parse macro!");
+                    return node;
+                }
+                Some(Token::COMMA) => {
                     self.lexer.next_token();
-                    node = Node::new(
-                        Kind::SET,
-                        None,
-                        Some(Box::new(node.clone())),
-                        Some(Box::new(self.expression())),
-                        None,
-                    );
+                    continue;
                 }
+                _ => {}
+            }
+
+            node = Node::new(
+                Kind::ARGS_ENUM,
+                None,
+                Some(Box::new(node)),
+                Some(Box::new(self.term())),
+                None,
+            );
+        }
+
+        self.lexer.next_token();
+
+        node
+    }
+
+    fn expression(&mut self) -> Node {
+        let node = self.binary(0);
+
+        // assignment gives no value, so it's allowed only as statement
+
+        if self.lexer.token == Some(Token::EQUAL) {
+            self.error("Assignment is not an expression");
+            self.expression();
+        }
+
+        node
+    }
+
+    // single '=' after expression statement is assignment
+
+    fn assignment(&mut self) -> Node {
+        let node = self.binary(0);
+
+        if self.lexer.token != Some(Token::EQUAL) {
+            return node;
+        }
+
+        if node.kind != Kind::VAR && node.kind != Kind::SLICE {
+            self.error("Cannot assign value to expression");
+            self.expression();
+
+            return node;
+        }
+
+        let span = node.span;
+        self.lexer.next_token();
+
+        Node::new(
+            Kind::SET,
+            None,
+            Some(Box::new(node)),
+            Some(Box::new(self.expression())),
+            None,
+        )
+        .at(span)
+    }
 
-                return node;
+    // statements in '{}', token after '}' is checked by caller
+
+    fn block(&mut self) -> Node {
+        let mut node = Node::new(Kind::EMPTY, None, None, None, None);
+        self.lexer.next_token();
+
+        while self.lexer.token.unwrap() != Token::RBRA {
+            if self.lexer.token == Some(Token::EOF) {
+                self.critical_error("'}' expected for ending block!");
+                break;
             }
-            _ => return self.test(),
+
+            node = Node::new(
+                Kind::SEQ,
+                None,
+                Some(Box::new(node.clone())),
+                Some(Box::new(self.statement())),
+                None,
+            )
         }
+
+        self.lexer.next_token();
+
+        node
+    }
+
+    fn loop_body(&mut self) -> Node {
+        self.loops += 1;
+        let body = self.statement();
+        self.loops -= 1;
+
+        body
     }
 
     fn statement(&mut self) -> Node {
-        let mut node = Node::new(Kind::EMPTY, None, None, None, None);
+        let mut node;
 
-        let token = self.lexer.token.clone().unwrap();
+        let token = self.lexer.token.unwrap();
+        let span = self.lexer.span;
 
         match token {
             Token::SEMICOLON => {
@@ -578,16 +743,6 @@ impl Parser {
 
                 self.lexer.next_token();
             }
-            Token::INPUT => {
-                self.lexer.next_token();
-                node = Node::new(
-                    Kind::INPUT,
-                    None,
-                    Some(Box::new(self.paren_expression())),
-                    None,
-                    None,
-                );
-            }
             //
             Token::IF => {
                 self.lexer.next_token();
@@ -604,6 +759,14 @@ impl Parser {
                 if self.lexer.token.unwrap() == Token::ELSE {
                     node.kind = Kind::IF_ELSE;
                     self.lexer.next_token();
+
+                    // `else if` chain: nested `if` already took the ';' after the chain
+
+                    if self.lexer.token == Some(Token::IF) {
+                        node.op3 = Some(Box::new(self.statement()));
+                        return node.at(span);
+                    }
+
                     node.op3 = Some(Box::new(self.statement()));
                 }
 
@@ -615,10 +778,12 @@ impl Parser {
                     Kind::WHILE,
                     None,
                     Some(Box::new(self.expression())),
-                    Some(Box::new(self.statement())),
+                    None,
                     None,
                 );
 
+                node.op2 = Some(Box::new(self.loop_body()));
+
                 self.lexer.next_token();
             }
             Token::FOR => {
@@ -627,7 +792,7 @@ impl Parser {
                 if self.lexer.token != Some(Token::ID) {
                     self.error("Variable name expected after 'for' keyword");
 
-                    while self.lexer.token != Some(Token::RBRA) {
+                    while !matches!(self.lexer.token, Some(Token::RBRA) | Some(Token::EOF)) {
                         self.lexer.next_token();
                     }
                 }
@@ -639,7 +804,7 @@ impl Parser {
                 if self.lexer.token != Some(Token::IN) {
                     self.error("Keyword 'in' expected after defining variable in 'for' cycle!");
 
-                    while self.lexer.token != Some(Token::RBRA) {
+                    while !matches!(self.lexer.token, Some(Token::RBRA) | Some(Token::EOF)) {
                         self.lexer.next_token();
                     }
                 };
@@ -647,7 +812,7 @@ impl Parser {
                 self.lexer.next_token();
 
                 node.op1 = Some(Box::new(self.expression()));
-                node.op2 = Some(Box::new(self.statement()));
+                node.op2 = Some(Box::new(self.loop_body()));
 
                 self.lexer.next_token();
             }
@@ -669,70 +834,83 @@ impl Parser {
 
                 match self.lexer.token {
                     Some(Token::LBRA) => {
+                        // loops around function don't continue inside its body
+
+                        let loops = std::mem::replace(&mut self.loops, 0);
                         node.op2 = Some(Box::new(self.statement()));
+                        self.loops = loops;
                     }
                     _ => {
                         self.error("Expected '{' after function define");
                     }
                 };
             }
+            Token::RETURN => {
+                self.lexer.next_token();
+
+                node = Node::new(Kind::RETURN, None, None, None, None);
+
+                if self.lexer.token != Some(Token::SEMICOLON) {
+                    node.op1 = Some(Box::new(self.expression()));
+                }
+
+                if self.lexer.token != Some(Token::SEMICOLON) {
+                    self.error("';' expected after return statement");
+                }
+                self.lexer.next_token();
+            }
+            Token::BREAK | Token::CONTINUE => {
+                let (kind, keyword) = match token {
+                    Token::BREAK => (Kind::BREAK, "break"),
+                    _ => (Kind::CONTINUE, "continue"),
+                };
+
+                node = Node::new(kind, None, None, None, None);
+
+                if self.loops == 0 {
+                    self.error(format!("Cannot use '{}' outside of loop!", keyword).as_str());
+                } else {
+                    self.lexer.next_token();
+                }
+
+                if self.lexer.token != Some(Token::SEMICOLON) {
+                    self.error(format!("';' expected after '{}'", keyword).as_str());
+                }
+                self.lexer.next_token();
+            }
             Token::OP => {
                 self.lexer.next_token();
 
                 node = Node::new(
                     Kind::OP_MACRO,
                     None,
-                    Some(Box::new(self.paren_arguments())),
+                    Some(Box::new(self.macro_arguments())),
                     None,
                     None,
                 );
             }
             Token::USING => {
                 self.lexer.next_token();
-                self.lexer.next_token();
 
                 if self.lexer.token != Some(Token::STR) {
                     self.error("Importing filename should be STR!");
 
-                    while self.lexer.token != Some(Token::SEMICOLON) {
+                    while !matches!(self.lexer.token, Some(Token::SEMICOLON) | Some(Token::EOF)) {
                         self.lexer.next_token();
                     }
 
                     return self.statement();
-                } else {
-                    let path_node = self.expression();
+                }
 
-                    node = Node::new(Kind::FILE_IMPORT, path_node.value, None, None, None);
+                let path_node = self.term();
 
-                    self.lexer.next_token();
+                node = Node::new(Kind::FILE_IMPORT, path_node.value, None, None, None);
 
-                    if self.lexer.token != Some(Token::SEMICOLON) {
-                        self.error("Expected ';' after import module");
-                    } else {
-                        self.lexer.next_token();
-                    }
-                }
+                self.expect(Token::SEMICOLON, "Expected ';' after import module");
             }
             //
             Token::LBRA => {
-                node = Node::new(Kind::EMPTY, None, None, None, None);
-                self.lexer.next_token();
-
-                while self.lexer.token.unwrap() != Token::RBRA {
-                    if self.lexer.token == Some(Token::EOF) {
-                        self.error("'}' expected for ending block!");
-                    }
-
-                    node = Node::new(
-                        Kind::SEQ,
-                        None,
-                        Some(Box::new(node.clone())),
-                        Some(Box::new(self.statement())),
-                        None,
-                    )
-                }
-
-                self.lexer.next_token();
+                node = self.block();
 
                 match self.lexer.token {
                     Some(Token::SEMICOLON) => {}
@@ -740,52 +918,34 @@ impl Parser {
                     _ => self.error("';' expected after '}'"),
                 }
             }
-            Token::LBRACK => {
-                node = Node::new(Kind::ARRAY, None, None, None, None);
-                self.lexer.next_token();
-
-                let mut temp_node = Node::new(Kind::EMPTY, None, None, None, None);
-
-                while self.lexer.token.unwrap() != Token::RBRACK {
-                    temp_node = Node::new(
-                        Kind::BRACK_ENUM,
-                        None,
-                        Some(Box::new(temp_node.clone())),
-                        Some(Box::new(self.expression())),
-                        None,
-                    );
-                }
-
-                node.op1 = Some(Box::new(temp_node));
-
-                self.lexer.next_token();
-            }
             //
             _ => {
                 node = Node::new(
                     Kind::EXPR,
                     None,
-                    Some(Box::new(self.expression())),
+                    Some(Box::new(self.assignment())),
                     None,
                     None,
                 );
 
-                if self.lexer.token.clone().unwrap() != Token::SEMICOLON {
+                if self.lexer.token.unwrap() != Token::SEMICOLON {
                     self.error("';' expected after expression");
                 }
                 self.lexer.next_token();
             }
         }
 
-        return node;
+        // statements point to their first token
+
+        return node.at(span);
     }
 
-    pub fn parse(&mut self) -> Vec<Node> {
+    pub fn parse(&mut self) -> Result<Vec<Node>, ParseError> {
         self.lexer.next_token();
 
         let mut statements = Vec::new();
 
-        while self.lexer.token.clone() != Some(Token::EOF) {
+        while self.lexer.token != Some(Token::EOF) {
             let stmt = self.statement();
             statements.push(stmt);
         }
@@ -796,6 +956,105 @@ impl Parser {
             }
         }
 
-        return statements;
+        if !self.errors.is_empty() {
+            return Err(ParseError {
+                diagnostics: std::mem::take(&mut self.errors),
+            });
+        }
+
+        return Ok(statements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Node>, ParseError> {
+        let lexer = LEXER::new(source.to_string(), "test.hiw".to_string());
+        Parser::new(lexer).parse()
+    }
+
+    // tree of single expression statement written with parens: `(ADD 1 (MULT 2 3))`
+
+    fn shape(source: &str) -> String {
+        let statements = parse(source).unwrap();
+        assert_eq!(statements.len(), 1);
+
+        fn write(node: &Node) -> String {
+            match (&node.kind, &node.value) {
+                (Kind::CONST, Some(Value::INT(value))) => value.to_string(),
+                (Kind::VAR, Some(Value::STR(name))) => name.clone(),
+                (kind, _) => {
+                    let operands = [&node.op1, &node.op2]
+                        .into_iter()
+                        .flatten()
+                        .map(|operand| write(operand))
+                        .collect::<Vec<String>>();
+
+                    format!("({:?} {})", kind, operands.join(" "))
+                }
+            }
+        }
+
+        write(statements[0].op1.as_ref().unwrap())
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        parse(source)
+            .unwrap_err()
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(shape("1 + 2 * 3;"), "(ADD 1 (MULT 2 3))");
+        assert_eq!(shape("1 * 2 - 3 / 4;"), "(SUB (MULT 1 2) (DIV 3 4))");
+        assert_eq!(shape("(1 + 2) * 3;"), "(MULT (ADD 1 2) 3)");
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(shape("1 - 2 - 3;"), "(SUB (SUB 1 2) 3)");
+        assert_eq!(shape("8 / 4 / 2;"), "(DIV (DIV 8 4) 2)");
+        assert_eq!(shape("a == b != c;"), "(NE (EQ a b) c)");
+        assert_eq!(shape("a || b || c;"), "(OR (OR a b) c)");
+    }
+
+    #[test]
+    fn comparison_binds_tighter_than_logic() {
+        assert_eq!(
+            shape("a < b && c == d || e;"),
+            "(OR (AND (LT a b) (EQ c d)) e)"
+        );
+        assert_eq!(shape("a + 1 >= b * 2;"), "(BE (ADD a 1) (MULT b 2))");
+        assert_eq!(shape("a == b < c;"), "(EQ a (LT b c))");
+    }
+
+    #[test]
+    fn unary_operators_bind_tightest() {
+        assert_eq!(shape("-a * b;"), "(MULT (SUB 0 a) b)");
+        assert_eq!(shape("!a && b;"), "(AND (NOT a) b)");
+        assert_eq!(shape("1 - -2;"), "(SUB 1 -2)");
+    }
+
+    #[test]
+    fn assignment_is_statement() {
+        assert_eq!(shape("a = 1 + 2;"), "(SET a (ADD 1 2))");
+        assert_eq!(shape("a[0] = b;"), "(SET (SLICE a 0) b)");
+    }
+
+    #[test]
+    fn assignment_inside_expression_is_error() {
+        let message = "Assignment is not an expression";
+
+        assert_eq!(errors("print(a = 2);"), [message]);
+        assert_eq!(errors("f(a = 2);"), [message]);
+        assert_eq!(errors("if a = 2 { b = 1; };"), [message]);
+        assert_eq!(errors("a = b = 2;"), [message]);
+        assert_eq!(errors("1 + 2 = 3;"), ["Cannot assign value to expression"]);
     }
 }
@@ -0,0 +1,236 @@
+// hiw-lang compiler
+// https://github.com/mealet/hiw-lang
+// ----------------------------------------
+// Copyright ©️ 2024, mealet.
+// Project licensed under the BSD-3 License
+// that can be found in LICENSE file.
+// ----------------------------------------
+
+// REPL - interactive mode. One compiler and one VM live for the whole session, so variables and
+// functions from previous inputs stay available
+
+use colored::Colorize;
+use hiw::{compiler::Compiler, lexer::Lexer, parser::Parser, Error, Value, VM};
+use std::io::Write;
+
+const REPL_FILENAME: &str = "<repl>";
+const HISTORY_FILENAME: &str = ".hiw_history";
+
+const HELP: &str = "| :type <expr> - show type of expression
+| :vars        - show global variables
+| :reset       - forget all variables and functions
+| :help        - show this message
+| :quit        - exit (Ctrl+D works too)";
+
+struct Repl {
+    compiler: Compiler,
+    vm: VM,
+}
+
+impl Repl {
+    fn new() -> Self {
+        Repl {
+            compiler: Compiler::new(REPL_FILENAME.to_string(), String::new()),
+            vm: VM::new(Vec::new()),
+        }
+    }
+
+    // compiling input after previous ones and running only the new part
+
+    fn evaluate(&mut self, source: &str, keep_result: bool) -> Result<Option<Value>, Error> {
+        let source = hiw::filereader::remove_comments(source.to_string());
+
+        let lexer = Lexer::new(source.clone(), REPL_FILENAME.to_string());
+        lexer.check()?;
+
+        let mut parser = Parser::new(lexer);
+        let abstract_syntax_tree = parser.parse()?;
+
+        // failed input must not leave half compiled code behind
+
+        let snapshot = self.compiler.clone();
+        let warnings_count = self.compiler.warnings.len();
+
+        let chunk = match self.compiler.compile_chunk(
+            REPL_FILENAME,
+            &source,
+            abstract_syntax_tree,
+            keep_result,
+        ) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                self.compiler = snapshot;
+                return Err(err.into());
+            }
+        };
+
+        for warning in &self.compiler.warnings[warnings_count..] {
+            eprintln!("{}", warning.render());
+        }
+
+        let byte_code = self.compiler.byte_code();
+
+        self.vm.program = byte_code.program;
+        self.vm.constants = byte_code.constants;
+        self.vm.functions = byte_code.functions;
+        self.vm.line_table = byte_code.line_table;
+
+        if let Err(err) = self.vm.run_from(chunk.start) {
+            self.vm.frames.clear();
+            self.vm.stack.clear();
+            return Err(err.into());
+        }
+
+        if chunk.has_result {
+            return Ok(self.vm.stack.pop());
+        }
+
+        Ok(None)
+    }
+
+    fn command(&mut self, input: &str) -> bool {
+        let (command, argument) = match input.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (input, ""),
+        };
+
+        match command {
+            ":type" => match self.evaluate(&with_semicolon(argument), true) {
+                Ok(Some(value)) => println!("{}", value.type_name()),
+                Ok(None) => eprintln!("{}", "':type' expects expression".red()),
+                Err(err) => eprintln!("{}", err.render()),
+            },
+            ":vars" => {
+                let mut names = self
+                    .vm
+                    .line_table
+                    .globals
+                    .iter()
+                    .filter(|global| !global.scoped)
+                    .map(|global| global.name.clone())
+                    .collect::<Vec<String>>();
+
+                names.sort();
+
+                for name in names {
+                    if let Some(value) = self.vm.get_global(&name) {
+                        println!("{} = {}", name, self.vm.value_to_string(value.clone()));
+                    }
+                }
+            }
+            ":reset" => {
+                *self = Repl::new();
+                println!("{}", "| Session is cleared".cyan());
+            }
+            ":help" => println!("{}", HELP.cyan()),
+            ":quit" | ":exit" => return false,
+            _ => eprintln!(
+                "{}",
+                format!("Unknown command '{}', see ':help'", command).red()
+            ),
+        }
+
+        true
+    }
+}
+
+// statement without ';' at the end is still accepted
+
+fn with_semicolon(input: &str) -> String {
+    let input = input.trim_end();
+
+    if input.ends_with(';') {
+        input.to_string()
+    } else {
+        format!("{};", input)
+    }
+}
+
+// depth of '{' blocks that are still open, braces inside strings are skipped
+
+fn open_blocks(input: &str) -> i32 {
+    let mut depth = 0;
+    let mut is_string = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => is_string = !is_string,
+            '\\' if is_string => {
+                chars.next();
+            }
+            '{' if !is_string => depth += 1,
+            '}' if !is_string => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
+
+fn open_history() -> Option<std::fs::File> {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .ok()?;
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(std::path::Path::new(&home).join(HISTORY_FILENAME))
+        .ok()
+}
+
+fn read_line(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    let _ = std::io::stdout().flush();
+
+    let mut line = String::new();
+
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+pub fn run() {
+    println!("{}", "| Interactive mode, type ':help' for commands".cyan());
+
+    let mut repl = Repl::new();
+    let mut history = open_history();
+
+    while let Some(mut input) = read_line(">> ") {
+        // reading lines until all blocks are closed
+
+        while open_blocks(&input) > 0 {
+            match read_line(".. ") {
+                Some(line) => input.push_str(&line),
+                None => break,
+            }
+        }
+
+        let input = input.trim();
+
+        if input.is_empty() {
+            continue;
+        }
+
+        if let Some(file) = history.as_mut() {
+            let _ = writeln!(file, "{}", input);
+        }
+
+        if input.starts_with(':') {
+            if !repl.command(input) {
+                break;
+            }
+            continue;
+        }
+
+        // `null` result (of function without return, for example) isn't printed
+
+        match repl.evaluate(&with_semicolon(input), true) {
+            Ok(Some(Value::NULL)) | Ok(None) => {}
+            Ok(Some(value)) => println!("{}", repl.vm.value_to_string(value)),
+            Err(err) => eprintln!("{}", err.render()),
+        }
+    }
+}
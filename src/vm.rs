@@ -9,32 +9,179 @@
 // VM (virtual machine) - low level "computer" that gives me tool for converting AST to byte code
 // and running it on this VM
 
-use std::collections::HashMap;
+use crate::diagnostic::{Diagnostic, Location, Phase, Span};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    io::{BufRead, Write},
+};
 
 type PROGRAM = Vec<Operations>;
 
-// WARNING: Compare all updates with binary compiler
+// WARNING: Compare all updates with bytecode format
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     INT(i32),
+    FLOAT(f64),
     STR(String),
     BOOL(bool),
+    NULL,
     ARRAY(Vec<Value>),
+    // pairs of key and value in order of insertion
+    MAP(Vec<(Value, Value)>),
+    FUNCTION(Closure),
 }
 
-#[derive(Debug, PartialEq, Eq)]
 pub struct VM {
     pub stack: Vec<Value>,
     pub program: PROGRAM,
-    pub variables: HashMap<String, Value>,
+    pub constants: Vec<Value>,
+    pub globals: Vec<Option<Value>>,
+    pub functions: HashMap<String, Function>,
+    pub natives: HashMap<String, Native>,
+    pub frames: Vec<Frame>,
+    pub line_table: LineTable,
+    input: Option<Box<dyn BufRead>>,
+    output: Box<dyn Write>,
+    pc: usize,
+    invokes: usize,
+    tasks: Vec<Task>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+// Call frame - created by every function call, keeps local variables and the place to return to
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub function: usize,
+    pub return_address: usize,
+    pub stack_base: usize,
+    pub locals: Vec<Option<Value>>,
+}
+
+// Operation which calls function value (`map`, `sort` with comparator and others): the function
+// runs in usual call frame at `depth`, when it returns its result continues the operation
+
+struct Task {
+    address: usize,
+    depth: usize,
+    function: Value,
+    state: TaskState,
+}
+
+enum TaskState {
+    MAP {
+        items: std::vec::IntoIter<Value>,
+        mapped: Vec<Value>,
+    },
+    FILTER {
+        items: std::vec::IntoIter<Value>,
+        item: Value,
+        filtered: Vec<Value>,
+    },
+    REDUCE {
+        items: std::vec::IntoIter<Value>,
+        accumulator: Value,
+    },
+    FIND {
+        items: std::vec::IntoIter<Value>,
+        name: &'static str,
+        stop: bool,
+    },
+    // stable bottom-up merge sort: runs of `width` items are merged from `start`, `left` and
+    // `right` point to the next items of both runs
+    SORT {
+        items: Vec<Value>,
+        merged: Vec<Value>,
+        width: usize,
+        start: usize,
+        left: usize,
+        right: usize,
+    },
+}
+
+// Next step of operation: call function with arguments or finish with result
+
+enum Step {
+    CALL(Vec<Value>),
+    DONE(Value),
+}
+
+const MAX_CALL_DEPTH: usize = 10000;
+const MAX_TRACE_FRAMES: usize = 16;
+const HOST_RETURN_ADDRESS: usize = usize::MAX;
+
+// Native function calling back into VM runs its own `execute` on the native stack, so nesting of
+// such calls is limited much lower than script calls
+
+const MAX_INVOKE_DEPTH: usize = 32;
+
+// Variables are numbered by compiler, this limit keeps broken byte code from allocating too much
+
+pub const MAX_SLOTS: usize = 65536;
+
+// Native function - Rust function registered by host, scripts call it like usual function
+
+pub type NativeFunction = fn(&mut VM, Vec<Value>) -> Result<Value, RuntimeError>;
+
+#[derive(Clone, Debug)]
+pub struct Native {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFunction,
+}
+
+// Line table - debug information from compiler: source position of every instruction, names of
+// functions by their addresses and names of variables by their slots
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LineTable {
+    pub files: Vec<SourceFile>,
+    pub lines: Vec<LineEntry>,
+    pub functions: Vec<(usize, String)>,
+    pub globals: Vec<GlobalName>,
+    pub locals: Vec<LocalNames>,
+}
+
+// Name of global slot, `scoped` globals are declared in block at top level and hidden outside it
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobalName {
+    pub name: String,
+    pub scoped: bool,
+}
+
+// Names of function locals, function code is in [function, end) range
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalNames {
+    pub function: usize,
+    pub end: usize,
+    pub names: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineEntry {
+    pub file: usize,
+    pub span: Span,
+}
+
+// Operation - fixed width instruction with inline operands. Values and names are indexes in the
+// constant pool, jumps are relative to the jump instruction
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Operations {
-    PUSH,
+    PUSH(u32),
     //
-    ARR,
+    ARR(u32),
+    MAP(u32),
     SLICE,
     //
     ADD,
@@ -42,15 +189,54 @@ pub enum Operations {
     DIV,
     MULT,
     //
-    VAR,
-    ARG(Value),
-    FETCH,
-    STORE,
+    FETCH(u32),
+    STORE(u32),
+    FETCH_LOCAL(u32),
+    STORE_LOCAL(u32),
+    FETCH_ITEM(u32, u32),
+    FETCH_LOCAL_ITEM(u32, u32),
+    EDIT(u32, u32),
+    EDIT_LOCAL(u32, u32),
+    //
+    CALL(u32, u32),
+    CALL_NATIVE(u32, u32),
+    CALL_INDIRECT(u32),
+    CALL_GLOBAL(u32, u32),
+    CLOSURE(u32, u32),
+    RET,
     //
     TYPE,
     LEN,
     TO_INT,
+    TO_FLOAT,
     TO_STR,
+    ROUND,
+    FLOOR,
+    //
+    SLICE_SET,
+    ARR_PUSH,
+    ARR_POP,
+    ARR_INSERT,
+    REMOVE,
+    JOIN,
+    CONTAINS,
+    KEYS,
+    VALUES,
+    ITER,
+    //
+    ARR_MAP,
+    FILTER,
+    REDUCE,
+    SORT,
+    REVERSE,
+    ENUMERATE,
+    ZIP,
+    ANY,
+    ALL,
+    MIN,
+    MAX,
+    SUM,
+    SLICE_RANGE,
     //
     PRINT,
     INPUT,
@@ -58,23 +244,98 @@ pub enum Operations {
     LT,
     BT,
     EQ,
+    NE,
+    LE,
+    BE,
+    NOT,
     //
-    JMP,
-    JZ,
-    JNZ,
+    JMP(i32),
+    JZ(i32),
+    JNZ(i32),
     //
-    DROP,
+    DROP(u32),
+    DROP_LOCAL(u32),
+    DUP(u32),
     POP,
     CLEAN,
     HALT,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Function {
     pub name: Value,
     pub arguments: Vec<Value>,
-    pub program: PROGRAM,
-    pub jump_codes: Vec<usize>,
+    pub address: usize,
+}
+
+// Function value - compiled function with copies of enclosing variables it uses, copies become
+// its first locals on every call
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Closure {
+    pub address: usize,
+    pub arity: usize,
+    pub captures: Vec<Value>,
+}
+
+// Runtime error - stops the program, diagnostic already knows position and call stack
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    pub diagnostic: Box<Diagnostic>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.diagnostic)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::INT(_) => "INT",
+            Value::FLOAT(_) => "FLOAT",
+            Value::STR(_) => "STR",
+            Value::BOOL(_) => "BOOL",
+            Value::NULL => "NULL",
+            Value::ARRAY(_) => "ARRAY",
+            Value::MAP(_) => "MAP",
+            Value::FUNCTION(_) => "FUNCTION",
+        }
+    }
+}
+
+impl LineTable {
+    pub fn location(&self, address: usize) -> Option<Location> {
+        let entry = self.lines.get(address)?;
+        let file = self.files.get(entry.file)?;
+
+        // slots generated without any node (like final HALT) have no position
+
+        if entry.span.line == 0 {
+            return None;
+        }
+
+        Some(Location::new(&file.name, &file.source, entry.span))
+    }
+
+    pub fn function_name(&self, address: usize) -> Option<&str> {
+        self.functions
+            .iter()
+            .find(|(function_address, _)| *function_address == address)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn local_name(&self, function: usize, slot: u32) -> Option<&str> {
+        self.locals
+            .iter()
+            .find(|locals| locals.function == function)
+            .and_then(|locals| locals.names.get(slot as usize))
+            .map(|name| name.as_str())
+    }
 }
 
 impl VM {
@@ -82,17 +343,218 @@ impl VM {
         VM {
             stack: Vec::new(),
             program,
-            variables: HashMap::new(),
+            constants: Vec::new(),
+            globals: Vec::new(),
+            functions: HashMap::new(),
+            natives: HashMap::new(),
+            frames: Vec::new(),
+            line_table: LineTable::default(),
+            input: None,
+            output: Box::new(std::io::stdout()),
+            pc: 0,
+            invokes: 0,
+            tasks: Vec::new(),
+        }
+    }
+
+    // embedding helpers
+
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Some(Box::new(input));
+    }
+
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    pub fn register_native(&mut self, name: &str, arity: usize, function: NativeFunction) {
+        self.natives.insert(
+            name.to_string(),
+            Native {
+                name: name.to_string(),
+                arity,
+                function,
+            },
+        );
+    }
+
+    fn global_slot(&self, name: &str) -> Option<usize> {
+        self.line_table
+            .globals
+            .iter()
+            .position(|global| global.name == name && !global.scoped)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        let slot = self.global_slot(name)?;
+        self.globals.get(slot)?.as_ref()
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        let slot = match self.global_slot(name) {
+            Some(slot) => slot,
+            None => {
+                self.line_table.globals.push(GlobalName {
+                    name: name.to_string(),
+                    scoped: false,
+                });
+                self.line_table.globals.len() - 1
+            }
+        };
+
+        set_slot(&mut self.globals, slot as u32, value);
+    }
+
+    // calling hiw function from host, program should be already run to define globals
+
+    pub fn call(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, RuntimeError> {
+        let function = match self.functions.get(name) {
+            Some(function) => Value::FUNCTION(Closure {
+                address: function.address,
+                arity: function.arguments.len(),
+                captures: Vec::new(),
+            }),
+            None => {
+                return Err(self.error(format!("Function '{}' is not defined!", name).as_str()))
+            }
+        };
+
+        self.call_value(function, arguments)
+    }
+
+    // calling function value (returned by script or stored in variable), host call has no place
+    // to return to in the program
+
+    pub fn call_value(
+        &mut self,
+        function: Value,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        if self.invokes >= MAX_INVOKE_DEPTH {
+            return Err(self.error("Maximum depth of calls from native functions exceeded!"));
+        }
+
+        let saved_pc = self.pc;
+        let stack_base = self.stack.len();
+        let depth = self.frames.len();
+        let tasks = self.tasks.len();
+
+        self.stack.extend(arguments);
+        self.invokes += 1;
+
+        let result = self
+            .enter(function, self.stack.len() - stack_base, HOST_RETURN_ADDRESS)
+            .and_then(|_| self.execute(depth + 1));
+
+        self.invokes -= 1;
+
+        // state is restored even if function failed, so VM can be used again
+
+        let return_value = self.stack.pop();
+
+        self.frames.truncate(depth);
+        self.tasks.truncate(tasks);
+        self.stack.truncate(stack_base);
+        self.pc = saved_pc;
+
+        result?;
+        Ok(return_value.unwrap_or(Value::NULL))
+    }
+
+    // variables helpers
+
+    fn undefined(&self, slot: u32, local: bool) -> RuntimeError {
+        let name = match local {
+            true => self
+                .frames
+                .last()
+                .and_then(|frame| self.line_table.local_name(frame.function, slot)),
+            false => self
+                .line_table
+                .globals
+                .get(slot as usize)
+                .map(|global| global.name.as_str()),
+        };
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => format!("#{}", slot),
+        };
+
+        self.error(format!("Variable '{}' is not defined!", name).as_str())
+    }
+
+    // pushing frame for function value, its arguments are already on the stack
+
+    fn enter(
+        &mut self,
+        function: Value,
+        argc: usize,
+        return_address: usize,
+    ) -> Result<(), RuntimeError> {
+        let function = match function {
+            Value::FUNCTION(function) => function,
+            other => {
+                return Err(self.error(format!("Cannot call {}!", other.type_name()).as_str()))
+            }
+        };
+
+        if function.arity != argc {
+            let name = self
+                .line_table
+                .function_name(function.address)
+                .unwrap_or("<function>");
+
+            return Err(self.error(
+                format!(
+                    "Function '{}' takes {} argument(s), but {} given!",
+                    name, function.arity, argc
+                )
+                .as_str(),
+            ));
+        }
+
+        if function.address >= self.program.len() {
+            return Err(self.error(
+                format!("Function address '{}' is out of byte code!", function.address).as_str(),
+            ));
+        }
+
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(self.error("Maximum call depth exceeded!"));
+        }
+
+        if self.stack.len() - self.stack_base() < argc {
+            return Err(self.error("Stack error while calling function!"));
+        }
+
+        self.frames.push(Frame {
+            function: function.address,
+            return_address,
+            stack_base: self.stack.len() - argc,
+            locals: function.captures.into_iter().map(Some).collect(),
+        });
+        self.pc = function.address;
+
+        Ok(())
+    }
+
+    fn stack_base(&self) -> usize {
+        match self.frames.last() {
+            Some(frame) => frame.stack_base.min(self.stack.len()),
+            None => 0,
         }
     }
 
     // helping function
 
-    fn value_to_string(&self, val: Value) -> String {
+    pub fn value_to_string(&self, val: Value) -> String {
         match val {
             Value::INT(int) => int.to_string(),
+            Value::FLOAT(float) => format!("{:?}", float),
             Value::STR(string) => format!("\"{}\"", string),
             Value::BOOL(bool) => bool.to_string(),
+            Value::NULL => "null".to_string(),
             Value::ARRAY(arr) => {
                 let mut _vector = Vec::new();
                 for item in arr {
@@ -101,39 +563,727 @@ impl VM {
 
                 format!("[{}]", _vector.join(", "))
             }
+            Value::MAP(map) => {
+                let pairs = map
+                    .into_iter()
+                    .map(|(key, value)| {
+                        format!("{}: {}", self.value_to_string(key), self.value_to_string(value))
+                    })
+                    .collect::<Vec<String>>();
+
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Value::FUNCTION(function) => match self.line_table.function_name(function.address) {
+                Some(name) => format!("<function {}>", name),
+                None => "<function>".to_string(),
+            },
         }
     }
 
-    // main
+    fn values_equal(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::INT(a), Value::FLOAT(b)) => *a as f64 == *b,
+            (Value::FLOAT(a), Value::INT(b)) => *a == *b as f64,
+            (Value::ARRAY(a), Value::ARRAY(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(left_item, right_item)| self.values_equal(left_item, right_item))
+            }
+
+            // maps are equal with same pairs in any order
 
-    fn error(&self, message: &str) {
-        eprintln!("{} {}", "\x1b[31m[RuntimeError]\x1b[0m", message);
-        std::process::exit(1);
+            (Value::MAP(a), Value::MAP(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(key, left_value)| {
+                        b.iter().any(|(right_key, right_value)| {
+                            key == right_key && self.values_equal(left_value, right_value)
+                        })
+                    })
+            }
+            _ => left == right,
+        }
     }
 
-    pub fn run(&mut self) -> Result<(), &str> {
-        let mut pc: usize = 0;
+    // ordering for `<`, `>`, `<=` and `>=`: numbers by value, strings lexicographically, false
+    // before true, arrays element by element. `None` if values are unordered (NaN)
+
+    fn compare(&self, left: &Value, right: &Value) -> Result<Option<Ordering>, RuntimeError> {
+        let ordering = match (left, right) {
+            (Value::INT(a), Value::INT(b)) => Some(a.cmp(b)),
+            (Value::INT(a), Value::FLOAT(b)) => (*a as f64).partial_cmp(b),
+            (Value::FLOAT(a), Value::INT(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::FLOAT(a), Value::FLOAT(b)) => a.partial_cmp(b),
+            (Value::STR(a), Value::STR(b)) => Some(a.cmp(b)),
+            (Value::BOOL(a), Value::BOOL(b)) => Some(a.cmp(b)),
+            (Value::ARRAY(a), Value::ARRAY(b)) => {
+                for (left_item, right_item) in a.iter().zip(b.iter()) {
+                    match self.compare(left_item, right_item)? {
+                        Some(Ordering::Equal) => {}
+                        ordering => return Ok(ordering),
+                    }
+                }
 
-        loop {
-            let mut arg = Operations::ARG(Value::INT(0));
-            let mut subarg = Operations::ARG(Value::INT(0));
+                Some(a.len().cmp(&b.len()))
+            }
+            _ => {
+                return Err(self.error(
+                    format!(
+                        "Cannot compare {} and {}. Unexpected types.",
+                        left.type_name(),
+                        right.type_name()
+                    )
+                    .as_str(),
+                ))
+            }
+        };
+
+        Ok(ordering)
+    }
+
+    // position in array or string of `length` items, negative and too big indexes are errors
+
+    fn index(&self, index: &Value, length: usize) -> Result<usize, RuntimeError> {
+        match index {
+            Value::INT(index) if *index < 0 => {
+                Err(self.error(format!("Negative index {}!", index).as_str()))
+            }
+            Value::INT(index) if *index as usize >= length => Err(self.error(
+                format!("Index {} is out of bounds (length is {})!", index, length).as_str(),
+            )),
+            Value::INT(index) => Ok(*index as usize),
+            _ => Err(self.error("Cannot get slice of non-integer index!")),
+        }
+    }
+
+    // values from the top of stack in order they were pushed
+
+    fn operands(&mut self, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        if self.stack.len() - self.stack_base() < count {
+            return Err(self.error("Stack error: no value to take!"));
+        }
+
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    // changing container by array method or SLICE_SET, container stays the same if it fails
+
+    fn change(
+        &self,
+        container: &mut Value,
+        operation: Operations,
+        operands: Vec<Value>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let mut operands = operands.into_iter();
+        let mut operand = || operands.next().unwrap_or(Value::NULL);
+
+        match operation {
+            Operations::SLICE_SET => {
+                let index = operand();
+                let value = operand();
+
+                // new key is added to the end of map
+
+                match container {
+                    Value::ARRAY(array) => {
+                        let index = self.index(&index, array.len())?;
+                        array[index] = value;
+                    }
+                    Value::MAP(map) => {
+                        let key = self.map_key(index)?;
+
+                        match map.iter().position(|(map_key, _)| *map_key == key) {
+                            Some(position) => map[position].1 = value,
+                            None => map.push((key, value)),
+                        }
+                    }
+                    other => {
+                        return Err(self.error(
+                            format!("Cannot assign to index of {}!", other.type_name()).as_str(),
+                        ))
+                    }
+                }
+
+                Ok(None)
+            }
+            Operations::ARR_PUSH => {
+                let item = operand();
+                let array = self.array_mut(container, "push")?;
+
+                array.push(item);
+                Ok(Some(Value::INT(array.len() as i32)))
+            }
+            Operations::ARR_POP => match self.array_mut(container, "pop")?.pop() {
+                Some(item) => Ok(Some(item)),
+                None => Err(self.error("Cannot pop from empty array!")),
+            },
+            Operations::ARR_INSERT => {
+                let index = operand();
+                let item = operand();
+                let array = self.array_mut(container, "insert")?;
+
+                // inserting right after the last item is allowed
+
+                let index = match index {
+                    Value::INT(index) if index as usize == array.len() => array.len(),
+                    index => self.index(&index, array.len())?,
+                };
+
+                array.insert(index, item);
+                Ok(Some(Value::INT(array.len() as i32)))
+            }
+            Operations::REMOVE => {
+                let index = operand();
 
-            if pc < self.program.len() - 1 {
-                arg = self.program[pc + 1].clone();
+                match container {
+                    Value::MAP(map) => {
+                        let position = self.map_position(map, &index)?;
+                        Ok(Some(map.remove(position).1))
+                    }
+                    container => {
+                        let array = self.array_mut(container, "remove")?;
+                        let index = self.index(&index, array.len())?;
 
-                if pc < self.program.len() - 2 {
-                    subarg = self.program[pc + 2].clone();
+                        Ok(Some(array.remove(index)))
+                    }
                 }
             }
+            _ => Err(self.error("Operation cannot change variable!")),
+        }
+    }
+
+    // changing item of container by path of indexes (keys)
+
+    fn edit(
+        &self,
+        container: &mut Value,
+        path: &[Value],
+        operation: Operations,
+        operands: Vec<Value>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let (index, path) = match path.split_first() {
+            Some(first) => first,
+            None => return self.change(container, operation, operands),
+        };
+
+        let item = match container {
+            Value::ARRAY(array) => {
+                let index = self.index(index, array.len())?;
+                &mut array[index]
+            }
+            Value::MAP(map) => {
+                let position = self.map_position(map, index)?;
+                &mut map[position].1
+            }
+            other => {
+                return Err(self.error(
+                    format!("Cannot assign to index of {}!", other.type_name()).as_str(),
+                ))
+            }
+        };
+
+        self.edit(item, path, operation, operands)
+    }
+
+    // string repeated `count` times, negative and too large counts are errors
+
+    fn repeat(&self, string: &str, count: i32) -> Result<String, RuntimeError> {
+        if count < 0 {
+            return Err(self.error("Cannot multiply string by negative number!"));
+        }
+
+        match string.len().checked_mul(count as usize) {
+            Some(length) if length <= isize::MAX as usize => Ok(string.repeat(count as usize)),
+            _ => Err(self.error("String is too long after multiplying!")),
+        }
+    }
+
+    // item of string, array or map by index (key)
+
+    fn item(&self, container: &Value, index: &Value) -> Result<Value, RuntimeError> {
+        let item = match container {
+            Value::STR(string) => {
+                let index = self.index(index, string.chars().count())?;
+                Value::STR(string.chars().nth(index).unwrap_or_default().to_string())
+            }
+            Value::ARRAY(array) => {
+                let index = self.index(index, array.len())?;
+                array[index].clone()
+            }
+            Value::MAP(map) => {
+                let position = self.map_position(map, index)?;
+                map[position].1.clone()
+            }
+
+            // number is sliced by `for i in 10`: `10[i]` is `i` up to 10
+
+            Value::INT(int) => {
+                let length = (*int as i64 + 1).max(0) as usize;
+                Value::INT(self.index(index, length)? as i32)
+            }
+            _ => {
+                return Err(self.error(
+                    "Cannot get slice from any type exclude STR, ARRAY, MAP and INT",
+                ))
+            }
+        };
+
+        Ok(item)
+    }
+
+    // item of nested containers, only the item itself is copied
+
+    fn item_at(&self, container: &Value, path: &[Value]) -> Result<Value, RuntimeError> {
+        let (index, path) = match path.split_first() {
+            Some((index, path)) if !path.is_empty() => (index, path),
+            Some((index, _)) => return self.item(container, index),
+            None => return Ok(container.clone()),
+        };
+
+        match container {
+            Value::ARRAY(array) => {
+                let index = self.index(index, array.len())?;
+                self.item_at(&array[index], path)
+            }
+            Value::MAP(map) => {
+                let position = self.map_position(map, index)?;
+                self.item_at(&map[position].1, path)
+            }
+            other => self.item_at(&self.item(other, index)?, path),
+        }
+    }
+
+    // map keys are compared exactly, so only STR, INT and BOOL can be keys
+
+    fn map_key(&self, key: Value) -> Result<Value, RuntimeError> {
+        match key {
+            Value::STR(_) | Value::INT(_) | Value::BOOL(_) => Ok(key),
+            other => Err(self.error(
+                format!(
+                    "MAP key must be STR, INT or BOOL, but got {}!",
+                    other.type_name()
+                )
+                .as_str(),
+            )),
+        }
+    }
+
+    fn map_position(&self, map: &[(Value, Value)], key: &Value) -> Result<usize, RuntimeError> {
+        match map.iter().position(|(map_key, _)| map_key == key) {
+            Some(position) => Ok(position),
+            None => Err(self.error(
+                format!("Key {} is not in map!", self.value_to_string(key.clone())).as_str(),
+            )),
+        }
+    }
+
+    fn array(&self, value: Value, function: &str) -> Result<Vec<Value>, RuntimeError> {
+        match value {
+            Value::ARRAY(array) => Ok(array),
+            other => Err(self.error(
+                format!("'{}' expects ARRAY, but got {}!", function, other.type_name()).as_str(),
+            )),
+        }
+    }
 
-            match self.program[pc] {
+    fn array_mut<'a>(
+        &self,
+        value: &'a mut Value,
+        function: &str,
+    ) -> Result<&'a mut Vec<Value>, RuntimeError> {
+        match value {
+            Value::ARRAY(array) => Ok(array),
+            other => Err(self.error(
+                format!("'{}' expects ARRAY, but got {}!", function, other.type_name()).as_str(),
+            )),
+        }
+    }
+
+    fn slice_bounds(
+        &self,
+        start: i32,
+        end: i32,
+        length: usize,
+    ) -> Result<(usize, usize), RuntimeError> {
+        if start < 0 || start > end || end as usize > length {
+            return Err(self.error(
+                format!("Cannot slice from {} to {} (length is {})!", start, end, length).as_str(),
+            ));
+        }
+
+        Ok((start as usize, end as usize))
+    }
+
+    fn callable(&self, value: Value, function: &str) -> Result<Value, RuntimeError> {
+        match value {
+            Value::FUNCTION(_) => Ok(value),
+            other => Err(self.error(
+                format!("'{}' expects FUNCTION, but got {}!", function, other.type_name())
+                    .as_str(),
+            )),
+        }
+    }
+
+    // operations calling function values
+
+    fn start(&mut self, function: Value, state: TaskState) -> Result<(), RuntimeError> {
+        self.tasks.push(Task {
+            address: self.pc,
+            depth: self.frames.len(),
+            function,
+            state,
+        });
+
+        self.resume(None)
+    }
+
+    // continuing operation with result of its function, next call goes to frame at task depth
+    // and returns after the operation, so stack trace shows the operation as caller
+
+    fn resume(&mut self, result: Option<Value>) -> Result<(), RuntimeError> {
+        let mut task = match self.tasks.pop() {
+            Some(task) => task,
+            None => return Err(self.error("No operation to continue!")),
+        };
+
+        self.pc = task.address;
+
+        match self.step(&mut task, result)? {
+            Step::CALL(arguments) => {
+                let function = task.function.clone();
+                let address = task.address;
+                let argc = arguments.len();
+
+                self.stack.extend(arguments);
+                self.tasks.push(task);
+                self.enter(function, argc, address + 1)
+            }
+            Step::DONE(value) => {
+                self.stack.push(value);
+                self.pc = task.address + 1;
+
+                Ok(())
+            }
+        }
+    }
+
+    fn step(&self, task: &mut Task, result: Option<Value>) -> Result<Step, RuntimeError> {
+        let comparator = task.function.clone();
+
+        match &mut task.state {
+            TaskState::MAP { items, mapped } => {
+                mapped.extend(result);
+
+                match items.next() {
+                    Some(item) => Ok(Step::CALL(vec![item])),
+                    None => Ok(Step::DONE(Value::ARRAY(std::mem::take(mapped)))),
+                }
+            }
+            TaskState::FILTER {
+                items,
+                item,
+                filtered,
+            } => {
+                if let Some(result) = result {
+                    let item = std::mem::replace(item, Value::NULL);
+
+                    if self.predicate(result, "filter")? {
+                        filtered.push(item);
+                    }
+                }
+
+                match items.next() {
+                    Some(next) => {
+                        *item = next.clone();
+                        Ok(Step::CALL(vec![next]))
+                    }
+                    None => Ok(Step::DONE(Value::ARRAY(std::mem::take(filtered)))),
+                }
+            }
+            TaskState::REDUCE { items, accumulator } => {
+                if let Some(result) = result {
+                    *accumulator = result;
+                }
+
+                let accumulator = std::mem::replace(accumulator, Value::NULL);
+
+                match items.next() {
+                    Some(item) => Ok(Step::CALL(vec![accumulator, item])),
+                    None => Ok(Step::DONE(accumulator)),
+                }
+            }
+            TaskState::FIND { items, name, stop } => {
+                if let Some(result) = result {
+                    if self.predicate(result, name)? == *stop {
+                        return Ok(Step::DONE(Value::BOOL(*stop)));
+                    }
+                }
+
+                match items.next() {
+                    Some(item) => Ok(Step::CALL(vec![item])),
+                    None => Ok(Step::DONE(Value::BOOL(!*stop))),
+                }
+            }
+            TaskState::SORT {
+                items,
+                merged,
+                width,
+                start,
+                left,
+                right,
+            } => {
+                let mut before = match result {
+                    Some(result) => Some(self.ordered(result)?),
+                    None => None,
+                };
+
+                loop {
+                    let length = items.len();
+
+                    if *width >= length {
+                        return Ok(Step::DONE(Value::ARRAY(std::mem::take(items))));
+                    }
+
+                    let middle = (*start + *width).min(length);
+                    let end = (*start + *width * 2).min(length);
+
+                    // right item goes first only if it's strictly before the left one
+
+                    if let Some(before) = before.take() {
+                        let next = if before { &mut *right } else { &mut *left };
+
+                        merged.push(std::mem::replace(&mut items[*next], Value::NULL));
+                        *next += 1;
+                    }
+
+                    if *left < middle && *right < end {
+                        if comparator != Value::NULL {
+                            let arguments = vec![items[*right].clone(), items[*left].clone()];
+                            return Ok(Step::CALL(arguments));
+                        }
+
+                        // without comparator items are ordered like with `<`
+
+                        let order = self.compare(&items[*right], &items[*left])?;
+                        before = Some(order == Some(Ordering::Less));
+                        continue;
+                    }
+
+                    let take = |item: &mut Value| std::mem::replace(item, Value::NULL);
+
+                    merged.extend(items[*left..middle].iter_mut().map(take));
+                    merged.extend(items[*right..end].iter_mut().map(take));
+
+                    for (offset, item) in merged.drain(..).enumerate() {
+                        items[*start + offset] = item;
+                    }
+
+                    *start = end;
+
+                    if *start >= length {
+                        *start = 0;
+                        *width *= 2;
+                    }
+
+                    *left = *start;
+                    *right = (*start + *width).min(length);
+                }
+            }
+        }
+    }
+
+    fn predicate(&self, result: Value, name: &str) -> Result<bool, RuntimeError> {
+        match result {
+            Value::BOOL(result) => Ok(result),
+            other => Err(self.error(
+                format!("'{}' function must return BOOL, but got {}!", name, other.type_name())
+                    .as_str(),
+            )),
+        }
+    }
+
+    // `sort` comparator gets two items and returns true (or negative INT) if the first one goes
+    // before the second
+
+    fn ordered(&self, result: Value) -> Result<bool, RuntimeError> {
+        match result {
+            Value::BOOL(result) => Ok(result),
+            Value::INT(result) => Ok(result < 0),
+            other => Err(self.error(
+                format!(
+                    "'sort' comparator must return BOOL or INT, but got {}!",
+                    other.type_name()
+                )
+                .as_str(),
+            )),
+        }
+    }
+
+    fn float_to_int(&self, float: f64) -> Result<Value, RuntimeError> {
+        if float.is_nan() || float < i32::MIN as f64 || float > i32::MAX as f64 {
+            return Err(self.error(format!("Cannot convert {:?} to INT!", float).as_str()));
+        }
+
+        Ok(Value::INT(float as i32))
+    }
+
+    // main
+
+    pub fn error(&self, message: &str) -> RuntimeError {
+        let mut diagnostic = Diagnostic::error(Phase::Runtime, message);
+
+        if let Some(location) = self.line_table.location(self.pc) {
+            diagnostic = diagnostic.located(location);
+        }
+
+        for line in self.stack_trace() {
+            diagnostic = diagnostic.with_note(line);
+        }
+
+        RuntimeError {
+            diagnostic: Box::new(diagnostic),
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.error("Stack error: no value to take!")),
+        }
+    }
+
+    // call stack of hiw functions, innermost call goes first
+
+    fn stack_trace(&self) -> Vec<String> {
+        let mut trace = Vec::new();
+        let mut address = self.pc;
+
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            if depth < MAX_TRACE_FRAMES {
+                let name = self
+                    .line_table
+                    .function_name(frame.function)
+                    .unwrap_or("<unknown>");
+
+                trace.push(format!("in '{}'{}", name, self.trace_position(address)));
+            }
+
+            // returning goes to the instruction after call
+
+            address = frame.return_address.saturating_sub(1);
+        }
+
+        if self.frames.len() > MAX_TRACE_FRAMES {
+            trace.push(format!(
+                "... {} more calls",
+                self.frames.len() - MAX_TRACE_FRAMES
+            ));
+        }
+
+        match self.frames.first() {
+            Some(frame) if frame.return_address == HOST_RETURN_ADDRESS => {
+                trace.push("called from host".to_string());
+            }
+            Some(_) => trace.push(format!("in <main>{}", self.trace_position(address))),
+            None => {}
+        }
+
+        trace
+    }
+
+    fn trace_position(&self, address: usize) -> String {
+        match self.line_table.location(address) {
+            Some(location) => format!(" at {}:{}", location.file, location.span.line),
+            None => String::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.link_natives()?;
+        self.run_from(0)
+    }
+
+    // running from the given address, used to continue program with new code (natives there are
+    // checked only when called, because functions can be defined later)
+
+    pub fn run_from(&mut self, address: usize) -> Result<(), RuntimeError> {
+        self.pc = address;
+        self.tasks.clear();
+        self.execute(0)
+    }
+
+    // natives are registered after compiling, so their calls are checked before running
+
+    fn link_natives(&mut self) -> Result<(), RuntimeError> {
+        for address in 0..self.program.len() {
+            if let Operations::CALL_NATIVE(name, argc) = self.program[address] {
+                self.pc = address;
+
+                let name = self.name(name)?;
+
+                let message = match self.natives.get(name) {
+                    None => format!("Function '{}' is not defined!", name),
+                    Some(native) if native.arity > argc as usize => {
+                        format!("Not enough arguments for calling '{}' function!", name)
+                    }
+                    Some(native) if native.arity < argc as usize => {
+                        format!("Too much arguments for '{}' function!", name)
+                    }
+                    Some(_) => continue,
+                };
+
+                return Err(self.error(message.as_str()));
+            }
+        }
+
+        Ok(())
+    }
+
+    // constant pool helpers, names of variables and natives are STR constants
+
+    fn constant(&self, index: u32) -> Result<&Value, RuntimeError> {
+        match self.constants.get(index as usize) {
+            Some(value) => Ok(value),
+            None => Err(self.error(format!("Constant '{}' is out of pool!", index).as_str())),
+        }
+    }
+
+    fn name(&self, index: u32) -> Result<&str, RuntimeError> {
+        match self.constant(index)? {
+            Value::STR(name) if !name.is_empty() => Ok(name),
+            _ => Err(self.error("Variable name must be alphanumeric!")),
+        }
+    }
+
+    fn jump(&mut self, offset: i32) -> Result<(), RuntimeError> {
+        let target = self.pc as i64 + offset as i64;
+
+        if target < 0 || target >= self.program.len() as i64 {
+            return Err(self.error(
+                format!("Jump Code '{}' is out of byte code!", offset).as_str(),
+            ));
+        }
+
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    // executing until HALT or until frame at `stop_depth` returns
+
+    fn execute(&mut self, stop_depth: usize) -> Result<(), RuntimeError> {
+        loop {
+            match self.program[self.pc] {
                 Operations::ADD => {
-                    let _b = self.stack.pop().expect("Stack error");
-                    let _a = self.stack.pop().expect("Stack error");
+                    let _b = self.pop()?;
+                    let _a = self.pop()?;
 
                     match (_a, _b) {
                         // Both same types
-                        (Value::INT(a), Value::INT(b)) => self.stack.push(Value::INT(a + b)),
+                        (Value::INT(a), Value::INT(b)) => match a.checked_add(b) {
+                            Some(result) => self.stack.push(Value::INT(result)),
+                            None => return Err(self.error("Integer overflow while adding!")),
+                        },
+                        (Value::FLOAT(a), Value::FLOAT(b)) => self.stack.push(Value::FLOAT(a + b)),
                         (Value::STR(a), Value::STR(b)) => {
                             self.stack.push(Value::STR(format!("{}{}", a, b)));
                         }
@@ -141,7 +1291,7 @@ impl VM {
                             let mut _temp_a: Vec<Value> = a.clone();
                             let mut _temp_b: Vec<Value> = b.clone();
 
-                            let _ = _temp_a.append(&mut _temp_b);
+                            _temp_a.append(&mut _temp_b);
 
                             self.stack.push(Value::ARRAY(_temp_a));
                         }
@@ -156,6 +1306,14 @@ impl VM {
                             self.stack.push(Value::BOOL(boolean_value));
                         }
 
+                        // INT and FLOAT
+                        (Value::INT(a), Value::FLOAT(b)) => {
+                            self.stack.push(Value::FLOAT(a as f64 + b));
+                        }
+                        (Value::FLOAT(a), Value::INT(b)) => {
+                            self.stack.push(Value::FLOAT(a + b as f64));
+                        }
+
                         // INT and STR
                         (Value::INT(a), Value::STR(b)) => {
                             self.stack.push(Value::STR(format!("{}{}", a, b)));
@@ -164,6 +1322,14 @@ impl VM {
                             self.stack.push(Value::STR(format!("{}{}", a, b)));
                         }
 
+                        // FLOAT and STR
+                        (Value::FLOAT(a), Value::STR(b)) => {
+                            self.stack.push(Value::STR(format!("{:?}{}", a, b)));
+                        }
+                        (Value::STR(a), Value::FLOAT(b)) => {
+                            self.stack.push(Value::STR(format!("{}{:?}", a, b)));
+                        }
+
                         // BOOL and STR
                         (Value::BOOL(a), Value::STR(b)) => {
                             self.stack.push(Value::STR(format!("{}{}", a, b)));
@@ -172,6 +1338,14 @@ impl VM {
                             self.stack.push(Value::STR(format!("{}{}", a, b)));
                         }
 
+                        // NULL and STR
+                        (Value::NULL, Value::STR(b)) => {
+                            self.stack.push(Value::STR(format!("null{}", b)));
+                        }
+                        (Value::STR(a), Value::NULL) => {
+                            self.stack.push(Value::STR(format!("{}null", a)));
+                        }
+
                         // ARRAY and STR
                         (Value::ARRAY(a), Value::STR(b)) => {
                             let mut values_array: Vec<String> = Vec::new();
@@ -179,9 +1353,13 @@ impl VM {
                             for item in a {
                                 let printable_value = match item {
                                     Value::INT(i) => &i.to_string(),
+                                    Value::FLOAT(f) => &format!("{:?}", f),
                                     Value::STR(s) => &format!("\"{}\"", s),
                                     Value::BOOL(b) => &b.to_string(),
+                                    Value::NULL => &("null".to_string()),
                                     Value::ARRAY(_) => &("ARRAY[]".to_string()),
+                                    Value::MAP(_) => &("MAP{}".to_string()),
+                                    Value::FUNCTION(_) => &("FUNCTION".to_string()),
                                 };
 
                                 values_array.push(printable_value.clone());
@@ -197,9 +1375,13 @@ impl VM {
                             for item in b {
                                 let printable_value = match item {
                                     Value::INT(i) => &i.to_string(),
+                                    Value::FLOAT(f) => &format!("{:?}", f),
                                     Value::STR(s) => &format!("\"{}\"", s),
                                     Value::BOOL(b) => &b.to_string(),
+                                    Value::NULL => &("null".to_string()),
                                     Value::ARRAY(_) => &("ARRAY[]".to_string()),
+                                    Value::MAP(_) => &("MAP{}".to_string()),
+                                    Value::FUNCTION(_) => &("FUNCTION".to_string()),
                                 };
 
                                 values_array.push(printable_value.clone());
@@ -211,40 +1393,60 @@ impl VM {
                         }
 
                         // Other values we cannot implement
-                        _ => self.error("Cannot add not implemented values!"),
+                        _ => return Err(self.error("Cannot add not implemented values!")),
                     }
 
-                    pc += 1
+                    self.pc += 1
                 }
                 Operations::SUB => {
-                    let _b = self.stack.pop().expect("Stack error");
-                    let _a = self.stack.pop().expect("Stack error");
+                    let _b = self.pop()?;
+                    let _a = self.pop()?;
 
                     match (_a, _b) {
-                        (Value::INT(a), Value::INT(b)) => {
-                            self.stack.push(Value::INT(a - b));
+                        (Value::INT(a), Value::INT(b)) => match a.checked_sub(b) {
+                            Some(result) => self.stack.push(Value::INT(result)),
+                            None => return Err(self.error("Integer overflow while substracting!")),
+                        },
+                        (Value::FLOAT(a), Value::FLOAT(b)) => self.stack.push(Value::FLOAT(a - b)),
+                        (Value::INT(a), Value::FLOAT(b)) => {
+                            self.stack.push(Value::FLOAT(a as f64 - b));
                         }
-                        _ => self.error("Cannot substract types which doesn't implemented!"),
+                        (Value::FLOAT(a), Value::INT(b)) => {
+                            self.stack.push(Value::FLOAT(a - b as f64));
+                        }
+                        _ => return Err(self.error("Cannot substract types which doesn't implemented!")),
                     };
 
-                    pc += 1
+                    self.pc += 1
                 }
                 Operations::MULT => {
-                    let _b = self.stack.pop().expect("Stack error");
-                    let _a = self.stack.pop().expect("Stack error");
+                    let _b = self.pop()?;
+                    let _a = self.pop()?;
 
                     match (_a, _b) {
                         // Same type
-                        (Value::INT(a), Value::INT(b)) => {
-                            self.stack.push(Value::INT(a * b));
+                        (Value::INT(a), Value::INT(b)) => match a.checked_mul(b) {
+                            Some(result) => self.stack.push(Value::INT(result)),
+                            None => return Err(self.error("Integer overflow while multiplying!")),
+                        },
+                        (Value::FLOAT(a), Value::FLOAT(b)) => self.stack.push(Value::FLOAT(a * b)),
+
+                        // INT and FLOAT
+                        (Value::INT(a), Value::FLOAT(b)) => {
+                            self.stack.push(Value::FLOAT(a as f64 * b));
+                        }
+                        (Value::FLOAT(a), Value::INT(b)) => {
+                            self.stack.push(Value::FLOAT(a * b as f64));
                         }
 
                         // INT and STR
                         (Value::INT(a), Value::STR(b)) => {
-                            self.stack.push(Value::STR(b.repeat(a as usize)));
+                            let _str = self.repeat(&b, a)?;
+                            self.stack.push(Value::STR(_str));
                         }
                         (Value::STR(a), Value::INT(b)) => {
-                            self.stack.push(Value::STR(a.repeat(b as usize)));
+                            let _str = self.repeat(&a, b)?;
+                            self.stack.push(Value::STR(_str));
                         }
 
                         // INT and ARRAY
@@ -270,498 +1472,944 @@ impl VM {
                         }
 
                         // Others
-                        _ => self.error("Cannot multiply types which doesn't implemented!"),
+                        _ => return Err(self.error("Cannot multiply types which doesn't implemented!")),
                     }
 
-                    pc += 1
+                    self.pc += 1
                 }
                 Operations::DIV => {
-                    let _b = self.stack.pop().expect("Stack error");
-                    let _a = self.stack.pop().expect("Stack error");
+                    let _b = self.pop()?;
+                    let _a = self.pop()?;
 
                     match (_a, _b) {
                         // Same type
                         (Value::INT(a), Value::INT(b)) => {
-                            self.stack.push(Value::INT(a / b));
+                            if b == 0 {
+                                return Err(self.error("Division by zero!"));
+                            }
+
+                            match a.checked_div(b) {
+                                Some(result) => self.stack.push(Value::INT(result)),
+                                None => return Err(self.error("Integer overflow while dividing!")),
+                            }
+                        }
+                        (Value::FLOAT(a), Value::FLOAT(b)) => self.stack.push(Value::FLOAT(a / b)),
+
+                        // INT and FLOAT
+                        (Value::INT(a), Value::FLOAT(b)) => {
+                            self.stack.push(Value::FLOAT(a as f64 / b));
+                        }
+                        (Value::FLOAT(a), Value::INT(b)) => {
+                            self.stack.push(Value::FLOAT(a / b as f64));
                         }
 
                         // INT and STR
                         (Value::STR(a), Value::INT(b)) => {
-                            if a.len() < 1 {
-                                self.error("Cannot divide string which length is less 2");
+                            if a.is_empty() {
+                                return Err(self.error("Cannot divide string which length is less 2"));
+                            }
+
+                            if b == 0 {
+                                return Err(self.error("Division by zero!"));
                             }
 
-                            let final_string_length = a.len() / b as usize;
-                            let _chars = a
-                                .clone()
-                                .chars()
-                                .into_iter()
-                                .map(|x| x.to_string())
-                                .collect::<Vec<String>>();
+                            if b < 0 {
+                                return Err(self.error("Cannot divide string by negative number!"));
+                            }
+
+                            let final_string_length = a.len() / b as usize;
+                            let _str = a.chars().take(final_string_length).collect::<String>();
+
+                            // FINALLY PUSHING IT TO STACK
+
+                            self.stack.push(Value::STR(_str));
+                        }
+
+                        // Others
+                        _ => return Err(self.error("Cannot divide types which doesn't implemented!")),
+                    }
+
+                    self.pc += 1
+                }
+                Operations::POP => {
+                    self.stack.pop();
+                    self.pc += 1
+                }
+                Operations::CLEAN => {
+                    let stack_base = self.stack_base();
+                    self.stack.truncate(stack_base);
+                    self.pc += 1;
+                }
+                Operations::DROP(slot) => {
+                    if let Some(global) = self.globals.get_mut(slot as usize) {
+                        *global = None;
+                    }
+
+                    self.pc += 1;
+                }
+                Operations::DROP_LOCAL(slot) => {
+                    if let Some(local) = self
+                        .frames
+                        .last_mut()
+                        .and_then(|frame| frame.locals.get_mut(slot as usize))
+                    {
+                        *local = None;
+                    }
+
+                    self.pc += 1;
+                }
+                Operations::PUSH(index) => {
+                    let value = self.constant(index)?.clone();
+                    self.stack.push(value);
+
+                    self.pc += 1
+                }
+                Operations::FETCH(slot) => {
+                    match self.globals.get(slot as usize) {
+                        Some(Some(value)) => self.stack.push(value.clone()),
+                        _ => return Err(self.undefined(slot, false)),
+                    }
+
+                    self.pc += 1;
+                }
+                Operations::STORE(slot) => {
+                    let stack_value = self.pop()?;
+                    set_slot(&mut self.globals, slot, stack_value);
 
-                            // Doing a crutch because rust cannot let me just use slices (cuz size is unknown at
-                            // compilation time 🤬)
+                    self.pc += 1
+                }
+                Operations::FETCH_LOCAL(slot) => {
+                    let value = self
+                        .frames
+                        .last()
+                        .and_then(|frame| frame.locals.get(slot as usize));
+
+                    match value {
+                        Some(Some(value)) => self.stack.push(value.clone()),
+                        _ => return Err(self.undefined(slot, true)),
+                    }
 
-                            let mut _str = String::new();
+                    self.pc += 1;
+                }
+                Operations::STORE_LOCAL(slot) => {
+                    // function arguments are bound to locals with it too
 
-                            for index in 0..final_string_length {
-                                _str.push_str(_chars[index].as_str());
-                            }
+                    let stack_value = self.pop()?;
 
-                            // FINALLY PUSHING IT TO STACK
+                    match self.frames.last_mut() {
+                        Some(frame) => set_slot(&mut frame.locals, slot, stack_value),
+                        None => return Err(self.error("Local variable outside of function!")),
+                    }
 
-                            self.stack.push(Value::STR(_str));
-                        }
+                    self.pc += 1
+                }
+                Operations::CALL(address, argc) => {
+                    if address as usize >= self.program.len() {
+                        return Err(self.error(
+                            format!("Function address '{}' is out of byte code!", address)
+                                .as_str(),
+                        ));
+                    }
 
-                        // Others
-                        _ => self.error("Cannot divide types which doesn't implemented!"),
+                    if self.frames.len() >= MAX_CALL_DEPTH {
+                        return Err(self.error("Maximum call depth exceeded!"));
                     }
 
-                    pc += 1
+                    self.frames.push(Frame {
+                        function: address as usize,
+                        return_address: self.pc + 1,
+                        stack_base: self.stack.len().saturating_sub(argc as usize),
+                        locals: Vec::new(),
+                    });
+
+                    self.pc = address as usize;
                 }
-                Operations::POP => {
-                    self.stack.pop();
-                    pc += 1
+                Operations::CALL_INDIRECT(argc) => {
+                    // function value is on the top of stack, over its arguments
+
+                    let function = self.pop()?;
+                    self.enter(function, argc as usize, self.pc + 1)?;
                 }
-                Operations::CLEAN => {
-                    let _ = self.stack.clear();
-                    pc += 1;
-                }
-                Operations::DROP => {
-                    match arg {
-                        Operations::ARG(Value::STR(val)) => {
-                            if self.variables.contains_key(&val) {
-                                self.variables.remove(&val);
-                            }
-                        }
-                        _ => {
-                            self.error("Dropping value isn't ID!");
-                        }
+                Operations::CALL_GLOBAL(slot, argc) => {
+                    // function value in global declared after the calling code
+
+                    let function = match self.globals.get(slot as usize) {
+                        Some(Some(value)) => value.clone(),
+                        _ => return Err(self.undefined(slot, false)),
                     };
-                    pc += 2;
+
+                    self.enter(function, argc as usize, self.pc + 1)?;
                 }
-                Operations::PUSH => {
-                    match arg {
-                        Operations::ARG(a) => {
-                            self.stack.push(a);
-                        }
-                        _ => {
-                            eprintln!("Error occured while managin' data in stack");
-                        }
-                    }
-
-                    pc += 2
-                }
-                Operations::VAR => {
-                    // if arg == Operations::ARG( Value::INT(arg) ) {
-                    //     eprintln!("Cannot create variable with number as a name!");
-                    // } else if arg.unwrap().len() < 1 {
-                    match arg {
-                        Operations::ARG(Value::INT(_)) => {
-                            self.error("Cannot create variable with NUMBER as a name!");
-                        }
-                        Operations::ARG(Value::STR(varname)) => {
-                            if varname.len() < 1 {
-                                self.error("Unexpected variable name!");
-                            } else {
-                                match subarg {
-                                    Operations::ARG(t) => {
-                                        self.variables.insert(varname, t);
-                                    }
-                                    _ => {
-                                        eprintln!(
-                                            "Cannot create variable {}! No values found!",
-                                            varname
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            self.error("Undefined variable name!");
-                        }
+                Operations::CLOSURE(index, count) => {
+                    let mut function = match self.constant(index)? {
+                        Value::FUNCTION(function) => function.clone(),
+                        _ => return Err(self.error("Constant is not a function!")),
+                    };
+
+                    let stack_base = self.stack_base();
+
+                    if self.stack.len() - stack_base < count as usize {
+                        return Err(self.error("Stack error while creating function!"));
                     }
 
-                    pc += 3;
+                    function.captures = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Value::FUNCTION(function));
+
+                    self.pc += 1;
                 }
-                Operations::FETCH => {
-                    match arg {
-                        Operations::ARG(Value::INT(_)) => {
-                            self.error("Cannot get data from variable NUMBER as a name!");
+                Operations::CALL_NATIVE(name, argc) => {
+                    let name = self.name(name)?;
+
+                    let function = match self.natives.get(name) {
+                        Some(native) => native.function,
+                        None => {
+                            return Err(self.error(
+                                format!("Function '{}' is not defined!", name).as_str(),
+                            ))
                         }
-                        Operations::ARG(Value::STR(varname)) => {
-                            if varname.len() < 1 {
-                                self.error("Unexpected variable name!");
-                            } else {
-                                if !(self.variables.contains_key(varname.as_str())) {
-                                    self.error(
-                                        format!("Variable '{}' is not defined!", varname).as_str(),
-                                    );
-                                }
+                    };
+
+                    let stack_base = self.stack_base();
+
+                    if self.stack.len() - stack_base < argc as usize {
+                        return Err(self.error("Stack error while calling native function!"));
+                    }
+
+                    let arguments = self.stack.split_off(self.stack.len() - argc as usize);
+                    let return_value = function(self, arguments)?;
 
-                                let variable_value =
-                                    self.variables.clone().get(&varname).unwrap().clone();
-                                let _ = self.stack.push(variable_value);
+                    self.stack.push(return_value);
+                    self.pc += 1;
+                }
+                Operations::RET => {
+                    let return_value = self.pop()?;
+
+                    match self.frames.pop() {
+                        Some(frame) => {
+                            // everything function left on the stack is dropped
+                            self.stack.truncate(frame.stack_base);
+                            self.stack.push(return_value);
+
+                            self.pc = frame.return_address;
+
+                            // function called by operation gives its result back to the operation
+
+                            if self
+                                .tasks
+                                .last()
+                                .is_some_and(|task| task.depth == self.frames.len())
+                            {
+                                let result = self.pop()?;
+                                self.resume(Some(result))?;
+                            } else if self.frames.len() < stop_depth {
+                                return Ok(());
                             }
                         }
-                        _ => {
-                            self.error("Variable name must be alphanumeric!");
-                        }
+                        None => return Err(self.error("Cannot return from outside of function!")),
                     }
+                }
+                Operations::TYPE => {
+                    let stack_value = self.pop()?;
+
+                    self.stack
+                        .push(Value::STR(stack_value.type_name().to_string()));
 
-                    pc += 2;
+                    self.pc += 1;
                 }
-                Operations::STORE => {
-                    match arg {
-                        Operations::ARG(Value::INT(_)) => {
-                            self.error("Cannot store data to variable which name is number!");
+                Operations::TO_INT => {
+                    let stack_value = self.pop()?;
+
+                    match stack_value {
+                        Value::INT(_) => self.stack.push(stack_value),
+                        Value::FLOAT(float) => {
+                            let int_value = self.float_to_int(float.trunc())?;
+                            self.stack.push(int_value);
                         }
-                        Operations::ARG(Value::STR(varname)) => {
-                            if varname.len() < 1 {
-                                self.error("Unexpected variable name!");
-                            } else {
-                                let stack_value = self.stack.pop().unwrap_or(Value::INT(0));
-                                self.variables.insert(varname, stack_value);
-                            }
+                        // string which isn't a number gives null
+
+                        Value::STR(string) => {
+                            match string.trim().parse::<i32>() {
+                                Ok(val) => self.stack.push(Value::INT(val)),
+                                Err(_) => self.stack.push(Value::NULL),
+                            };
                         }
                         _ => {
-                            self.error("Unexpected operation!");
+                            return Err(self.error(
+                                format!("Cannot convert {} to INT!", stack_value.type_name())
+                                    .as_str(),
+                            ))
                         }
-                    }
+                    };
 
-                    pc += 2
+                    self.pc += 1;
                 }
-                Operations::TYPE => {
-                    let stack_value = self.stack.pop().unwrap();
+                Operations::TO_FLOAT => {
+                    let stack_value = self.pop()?;
 
                     match stack_value {
-                        Value::INT(_) => self.stack.push(Value::STR("INT".to_string())),
-                        Value::STR(_) => self.stack.push(Value::STR("STR".to_string())),
-                        Value::BOOL(_) => self.stack.push(Value::STR("BOOL".to_string())),
-                        Value::ARRAY(_) => self.stack.push(Value::STR("ARRAY".to_string())),
+                        Value::INT(int) => self.stack.push(Value::FLOAT(int as f64)),
+                        Value::FLOAT(_) => self.stack.push(stack_value),
+                        Value::STR(string) => match string.trim().parse::<f64>() {
+                            Ok(val) => self.stack.push(Value::FLOAT(val)),
+                            Err(_) => self.stack.push(Value::NULL),
+                        },
+                        _ => {
+                            return Err(self.error(
+                                format!("Cannot convert {} to FLOAT!", stack_value.type_name())
+                                    .as_str(),
+                            ))
+                        }
                     };
 
-                    pc += 1;
+                    self.pc += 1;
                 }
-                Operations::TO_INT => {
-                    let stack_value = self.stack.pop().unwrap();
+                Operations::ROUND | Operations::FLOOR => {
+                    let stack_value = self.pop()?;
 
                     match stack_value {
                         Value::INT(_) => self.stack.push(stack_value),
-                        Value::STR(string) => {
-                            let try_parse = match string.trim().parse::<i32>() {
-                                Ok(val) => self.stack.push(Value::INT(val)),
-                                Err(_) => {
-                                    self.stack.push(Value::STR("INT_PARSE_ERROR".to_string()))
-                                }
+                        Value::FLOAT(float) => {
+                            let rounded = match self.program[self.pc] {
+                                Operations::ROUND => float.round(),
+                                _ => float.floor(),
                             };
+
+                            let int_value = self.float_to_int(rounded)?;
+                            self.stack.push(int_value);
                         }
-                        _ => self
-                            .stack
-                            .push(Value::STR("INT_PARSE_NOT_IMPLEMENTED".to_string())),
+                        _ => return Err(self.error(
+                            format!("Cannot round {} value!", self.value_to_string(stack_value))
+                                .as_str(),
+                        )),
                     };
 
-                    pc += 1;
+                    self.pc += 1;
                 }
                 Operations::TO_STR => {
-                    let stack_value = self.stack.pop().unwrap();
+                    let stack_value = self.pop()?;
 
-                    let _ = self
-                        .stack
+                    self.stack
                         .push(Value::STR(self.value_to_string(stack_value)));
 
-                    pc += 1;
+                    self.pc += 1;
                 }
                 Operations::LEN => {
-                    let stack_value = self.stack.pop().unwrap();
+                    let stack_value = self.pop()?;
 
                     match stack_value {
                         Value::INT(_) => self.stack.push(stack_value),
-                        Value::STR(str) => self.stack.push(Value::INT(str.len() as i32)),
+                        Value::STR(str) => self.stack.push(Value::INT(str.chars().count() as i32)),
                         Value::ARRAY(arr) => self.stack.push(Value::INT(arr.len() as i32)),
-                        _ => self.stack.push(Value::STR("LEN_NOT_COVERED".to_string())),
+                        Value::MAP(map) => self.stack.push(Value::INT(map.len() as i32)),
+                        _ => {
+                            return Err(self.error(
+                                format!("Cannot get length of {}!", stack_value.type_name())
+                                    .as_str(),
+                            ))
+                        }
                     }
 
-                    pc += 1;
+                    self.pc += 1;
                 }
                 Operations::PRINT => {
-                    let print_value = self.stack.pop().unwrap();
-                    println!("{}", self.value_to_string(print_value));
+                    let print_value = self.pop()?;
+                    let printable = self.value_to_string(print_value);
+
+                    if writeln!(self.output, "{}", printable).is_err() {
+                        return Err(self.error("Cannot write to output!"));
+                    }
 
-                    pc += 1;
+                    self.pc += 1;
                 }
                 Operations::INPUT => {
                     let mut input_string = String::new();
-                    let _ = std::io::stdin().read_line(&mut input_string);
+                    let _ = self.output.flush();
+
+                    // without custom input stdin is read directly, so its buffer stays shared
 
-                    self.stack.push(Value::STR(input_string.trim().to_string()));
+                    let read_result = match &mut self.input {
+                        Some(input) => input.read_line(&mut input_string),
+                        None => std::io::stdin().read_line(&mut input_string),
+                    };
+
+                    // end of input gives null
+
+                    match read_result {
+                        Ok(0) => self.stack.push(Value::NULL),
+                        Ok(_) => self.stack.push(Value::STR(input_string.trim().to_string())),
+                        Err(_) => return Err(self.error("Cannot read from input!")),
+                    }
 
-                    pc += 1;
+                    self.pc += 1;
                 }
-                Operations::JMP => {
-                    if let Operations::ARG(Value::INT(jump_code)) = arg {
-                        if jump_code > self.program.len() as i32 {
-                            self.error(
-                                format!("Jump Code '{}' is bigger than byte code!", jump_code)
-                                    .as_str(),
-                            );
-                            pc += 2;
-                        } else {
-                            if jump_code < 0 {
-                                let mut formatted_pc = pc.clone() as i32;
-                                formatted_pc += jump_code;
-
-                                pc = formatted_pc as usize;
-                            } else {
-                                pc += jump_code as usize;
-                            }
-                        }
-                    } else {
-                        self.error("Jump Code isn't number!");
-                        pc += 2;
+                Operations::JMP(offset) => self.jump(offset)?,
+                Operations::JZ(offset) | Operations::JNZ(offset) => {
+                    // JZ jumps when condition is true, JNZ when it is false
+
+                    let expected = matches!(self.program[self.pc], Operations::JZ(_));
+
+                    match self.pop()? {
+                        Value::BOOL(condition) if condition == expected => self.jump(offset)?,
+                        Value::BOOL(_) => self.pc += 1,
+                        _ => return Err(self.error("Comparsion result isn't boolean!")),
                     }
                 }
-                Operations::JZ => {
-                    if let Operations::ARG(Value::INT(jump_code)) = arg {
-                        if jump_code > self.program.len() as i32 {
-                            self.error(
-                                format!("Jump Code '{}' is bigger than byte code!", jump_code)
-                                    .as_str(),
-                            );
-                        } else {
-                            let stack_value = self.stack.pop().unwrap_or_else(|| {
-                                self.error("Stack error with boolean operation!");
-                                Value::BOOL(false)
-                            });
-                            if let Value::BOOL(unwrapped_value) = stack_value {
-                                if unwrapped_value == true {
-                                    if jump_code < 0 {
-                                        let mut formatted_pc = pc.clone() as i32;
-                                        formatted_pc += jump_code;
-
-                                        pc = formatted_pc as usize;
-                                    } else {
-                                        pc += jump_code as usize;
-                                    }
-                                } else {
-                                    pc += 2;
-                                }
-                            } else {
-                                self.error("Comparsion result isn't boolean!");
-                            }
+                Operations::LT | Operations::BT | Operations::LE | Operations::BE => {
+                    let right_stack = self.pop()?;
+                    let left_stack = self.pop()?;
+
+                    // unordered values (NaN) make every comparison false
+
+                    let ordering = self.compare(&left_stack, &right_stack)?;
+
+                    let result = match (self.program[self.pc], ordering) {
+                        (_, None) => false,
+                        (Operations::LT, Some(ordering)) => ordering == Ordering::Less,
+                        (Operations::BT, Some(ordering)) => ordering == Ordering::Greater,
+                        (Operations::LE, Some(ordering)) => ordering != Ordering::Greater,
+                        (_, Some(ordering)) => ordering != Ordering::Less,
+                    };
+
+                    self.stack.push(Value::BOOL(result));
+                    self.pc += 1
+                }
+                Operations::NOT => {
+                    match self.pop()? {
+                        Value::BOOL(value) => self.stack.push(Value::BOOL(!value)),
+                        value => {
+                            return Err(self.error(
+                                format!("Cannot apply '!' to {}!", value.type_name()).as_str(),
+                            ))
                         }
-                    } else {
-                        self.error("Jump Code isn't number!");
                     }
+
+                    self.pc += 1;
                 }
-                Operations::JNZ => {
-                    if let Operations::ARG(Value::INT(jump_code)) = arg {
-                        if jump_code as usize > self.program.len() {
-                            self.error(
-                                format!("Jump Code '{}' is bigger than byte code!", jump_code)
-                                    .as_str(),
-                            );
-                        } else {
-                            let stack_value = self.stack.pop().unwrap();
-                            if let Value::BOOL(unwrapped_value) = stack_value {
-                                if unwrapped_value != true {
-                                    if jump_code < 0 {
-                                        let mut formatted_pc = pc.clone() as i32;
-                                        formatted_pc += jump_code;
-
-                                        pc = formatted_pc as usize;
-                                    } else {
-                                        pc += jump_code as usize;
-                                    }
-                                } else {
-                                    pc += 2
-                                }
-                            } else {
-                                self.error("Comparsion result isn't boolean!");
-                            }
+                Operations::EQ | Operations::NE => {
+                    let right_stack = self.pop()?;
+                    let left_stack = self.pop()?;
+
+                    let equal = self.values_equal(&left_stack, &right_stack);
+                    let expected = self.program[self.pc] == Operations::EQ;
+
+                    self.stack.push(Value::BOOL(equal == expected));
+
+                    self.pc += 1;
+                }
+                Operations::MAP(count) => {
+                    // collecting map from N pairs of key and value at the top of stack
+
+                    let stack_base = self.stack_base();
+
+                    if (self.stack.len() - stack_base) / 2 < count as usize {
+                        return Err(self.error("Stack error while creating map!"));
+                    }
+
+                    let items = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut map: Vec<(Value, Value)> = Vec::with_capacity(count as usize);
+                    let mut items = items.into_iter();
+
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        let key = self.map_key(key)?;
+
+                        match map.iter().position(|(map_key, _)| *map_key == key) {
+                            Some(position) => map[position].1 = value,
+                            None => map.push((key, value)),
                         }
-                    } else {
-                        eprintln!("Argument must be number!");
                     }
+
+                    self.stack.push(Value::MAP(map));
+                    self.pc += 1;
                 }
-                Operations::LT => {
-                    let right_stack = self.stack.pop().unwrap();
-                    let left_stack = self.stack.pop().unwrap();
+                Operations::ARR(count) => {
+                    // collecting array from N values at the top of stack
 
-                    match (left_stack.clone(), right_stack.clone()) {
-                        (Value::INT(left), Value::INT(right)) => {
-                            if left < right {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
-                        }
-                        (Value::STR(left), Value::STR(right)) => {
-                            if left.len() < right.len() {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
-                        }
-                        (Value::ARRAY(left), Value::ARRAY(right)) => {
-                            if left.len() < right.len() {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
-                        }
+                    let stack_base = self.stack_base();
 
-                        // Array and Int
-                        (Value::ARRAY(left), Value::INT(right)) => {
-                            if (left.len() as i32) < right {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
-                        }
-                        (Value::INT(left), Value::ARRAY(right)) => {
-                            if (right.len() as i32) < left {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
-                        }
+                    if self.stack.len() - stack_base < count as usize {
+                        return Err(self.error("Stack error while creating array!"));
+                    }
 
-                        _ => {
-                            self.error(
+                    let array_result = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Value::ARRAY(array_result));
+
+                    self.pc += 1;
+                }
+                Operations::SLICE => {
+                    // Slice from value at the top of stack
+
+                    let index = self.pop()?;
+                    let slicable_object = self.pop()?;
+
+                    let item = self.item(&slicable_object, &index)?;
+
+                    self.stack.push(item);
+                    self.pc += 1;
+                }
+                Operations::FETCH_ITEM(slot, depth) | Operations::FETCH_LOCAL_ITEM(slot, depth) => {
+                    // item of variable by path of indexes without copying the containers
+
+                    let path = self.operands(depth as usize)?;
+                    let local = matches!(self.program[self.pc], Operations::FETCH_LOCAL_ITEM(..));
+
+                    let container = match local {
+                        true => self
+                            .frames
+                            .last()
+                            .and_then(|frame| frame.locals.get(slot as usize)),
+                        false => self.globals.get(slot as usize),
+                    };
+
+                    let item = match container {
+                        Some(Some(container)) => self.item_at(container, &path)?,
+                        _ => return Err(self.undefined(slot, local)),
+                    };
+
+                    self.stack.push(item);
+                    self.pc += 1;
+                }
+                Operations::DUP(count) => {
+                    let stack_base = self.stack_base();
+
+                    if self.stack.len() - stack_base < count as usize {
+                        return Err(self.error("Stack error while copying values!"));
+                    }
+
+                    let copied = self.stack[self.stack.len() - count as usize..].to_vec();
+                    self.stack.extend(copied);
+
+                    self.pc += 1;
+                }
+
+                // array methods and SLICE_SET leave changed container and result of method on
+                // the stack: [container, operands] -> [container, result]
+
+                Operations::SLICE_SET
+                | Operations::ARR_PUSH
+                | Operations::ARR_POP
+                | Operations::ARR_INSERT
+                | Operations::REMOVE => {
+                    let operation = self.program[self.pc];
+                    let operands = self.operands(change_operands(operation).unwrap_or(0))?;
+                    let mut container = self.pop()?;
+
+                    let result = self.change(&mut container, operation, operands)?;
+
+                    self.stack.push(container);
+
+                    if let Some(result) = result {
+                        self.stack.push(result);
+                    }
+
+                    self.pc += 1;
+                }
+                Operations::EDIT(slot, depth) | Operations::EDIT_LOCAL(slot, depth) => {
+                    // `a[i].push(x)` and `a[i][j] = x`: next operation changes item of variable
+                    // in place, indexes of the item are under operands of the operation
+
+                    let local = matches!(self.program[self.pc], Operations::EDIT_LOCAL(..));
+                    let operation = self.program.get(self.pc + 1).copied();
+
+                    let (operation, count) = match operation {
+                        Some(operation) => match change_operands(operation) {
+                            Some(count) => (operation, count),
+                            None => return Err(self.error("Operation cannot change variable!")),
+                        },
+                        None => return Err(self.error("Operation cannot change variable!")),
+                    };
+
+                    let operands = self.operands(count)?;
+                    let path = self.operands(depth as usize)?;
+
+                    // variable is taken out of its slot while it's changed, so it isn't copied
+
+                    let variable = match local {
+                        true => self
+                            .frames
+                            .last_mut()
+                            .and_then(|frame| frame.locals.get_mut(slot as usize)),
+                        false => self.globals.get_mut(slot as usize),
+                    };
+
+                    let mut variable = match variable.and_then(|variable| variable.take()) {
+                        Some(variable) => variable,
+                        None => return Err(self.undefined(slot, local)),
+                    };
+
+                    let result = self.edit(&mut variable, &path, operation, operands);
+
+                    match (local, self.frames.last_mut()) {
+                        (true, Some(frame)) => set_slot(&mut frame.locals, slot, variable),
+                        _ => set_slot(&mut self.globals, slot, variable),
+                    }
+
+                    if let Some(result) = result? {
+                        self.stack.push(result);
+                    }
+
+                    self.pc += 2;
+                }
+                Operations::KEYS | Operations::VALUES => {
+                    let keys = self.program[self.pc] == Operations::KEYS;
+
+                    let map = match self.pop()? {
+                        Value::MAP(map) => map,
+                        other => {
+                            return Err(self.error(
                                 format!(
-                                    "Cannot compare {:?} and {:?}. Unexpected types.",
-                                    left_stack, right_stack
+                                    "'{}' expects MAP, but got {}!",
+                                    if keys { "keys" } else { "values" },
+                                    other.type_name()
                                 )
                                 .as_str(),
-                            );
+                            ))
                         }
-                    }
+                    };
 
-                    pc += 1
+                    let items = map
+                        .into_iter()
+                        .map(|(key, value)| if keys { key } else { value })
+                        .collect();
+
+                    self.stack.push(Value::ARRAY(items));
+                    self.pc += 1;
                 }
-                Operations::BT => {
-                    let right_stack = self.stack.pop().unwrap();
-                    let left_stack = self.stack.pop().unwrap();
+                Operations::ITER => {
+                    // `for` goes over keys of map, characters of string and over other values
+                    // themselves
+
+                    match self.pop()? {
+                        Value::MAP(map) => self.stack.push(Value::ARRAY(
+                            map.into_iter().map(|(key, _)| key).collect(),
+                        )),
+                        Value::STR(string) => self.stack.push(Value::ARRAY(
+                            string
+                                .chars()
+                                .map(|char| Value::STR(char.to_string()))
+                                .collect(),
+                        )),
+                        other => self.stack.push(other),
+                    }
 
-                    match (left_stack.clone(), right_stack.clone()) {
-                        (Value::INT(left), Value::INT(right)) => {
-                            if left > right {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
+                    self.pc += 1;
+                }
+                Operations::JOIN => {
+                    let separator = match self.pop()? {
+                        Value::STR(separator) => separator,
+                        other => {
+                            return Err(self.error(
+                                format!(
+                                    "'join' separator must be STR, but got {}!",
+                                    other.type_name()
+                                )
+                                .as_str(),
+                            ))
                         }
-                        (Value::STR(left), Value::STR(right)) => {
-                            if left.len() > right.len() {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
+                    };
+
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "join")?;
+
+                    let items = array
+                        .into_iter()
+                        .map(|item| match item {
+                            Value::STR(string) => string,
+                            other => self.value_to_string(other),
+                        })
+                        .collect::<Vec<String>>();
+
+                    self.stack.push(Value::STR(items.join(&separator)));
+                    self.pc += 1;
+                }
+                Operations::CONTAINS => {
+                    let item = self.pop()?;
+
+                    let contains = match (self.pop()?, &item) {
+                        (Value::ARRAY(array), _) => array
+                            .iter()
+                            .any(|array_item| self.values_equal(array_item, &item)),
+                        (Value::MAP(map), _) => map.iter().any(|(key, _)| *key == item),
+                        (Value::STR(string), Value::STR(substring)) => {
+                            string.contains(substring.as_str())
                         }
-                        (Value::ARRAY(left), Value::ARRAY(right)) => {
-                            if left.len() > right.len() {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
+                        (other, _) => {
+                            return Err(self.error(
+                                format!(
+                                    "'contains' expects ARRAY or STR, but got {}!",
+                                    other.type_name()
+                                )
+                                .as_str(),
+                            ))
                         }
+                    };
 
-                        // Array and Int
-                        (Value::ARRAY(left), Value::INT(right)) => {
-                            if left.len() as i32 > right {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
+                    self.stack.push(Value::BOOL(contains));
+                    self.pc += 1;
+                }
+                Operations::ARR_MAP => {
+                    let function = self.pop()?;
+                    let function = self.callable(function, "map")?;
+
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "map")?;
+
+                    let mapped = Vec::with_capacity(array.len());
+
+                    self.start(
+                        function,
+                        TaskState::MAP {
+                            items: array.into_iter(),
+                            mapped,
+                        },
+                    )?;
+                }
+                Operations::FILTER => {
+                    let function = self.pop()?;
+                    let function = self.callable(function, "filter")?;
+
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "filter")?;
+
+                    self.start(
+                        function,
+                        TaskState::FILTER {
+                            items: array.into_iter(),
+                            item: Value::NULL,
+                            filtered: Vec::new(),
+                        },
+                    )?;
+                }
+                Operations::REDUCE => {
+                    let accumulator = self.pop()?;
+
+                    let function = self.pop()?;
+                    let function = self.callable(function, "reduce")?;
+
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "reduce")?;
+
+                    self.start(
+                        function,
+                        TaskState::REDUCE {
+                            items: array.into_iter(),
+                            accumulator,
+                        },
+                    )?;
+                }
+                Operations::SORT => {
+                    // comparator is null for natural order
+
+                    let compare = match self.pop()? {
+                        Value::NULL => Value::NULL,
+                        other => self.callable(other, "sort")?,
+                    };
+
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "sort")?;
+
+                    let right = array.len().min(1);
+
+                    self.start(
+                        compare,
+                        TaskState::SORT {
+                            items: array,
+                            merged: Vec::new(),
+                            width: 1,
+                            start: 0,
+                            left: 0,
+                            right,
+                        },
+                    )?;
+                }
+                Operations::REVERSE => {
+                    match self.pop()? {
+                        Value::ARRAY(mut array) => {
+                            array.reverse();
+                            self.stack.push(Value::ARRAY(array));
                         }
-                        (Value::INT(left), Value::ARRAY(right)) => {
-                            if right.len() as i32 > left {
-                                self.stack.push(Value::BOOL(true));
-                            } else {
-                                self.stack.push(Value::BOOL(false));
-                            }
+                        Value::STR(string) => {
+                            self.stack.push(Value::STR(string.chars().rev().collect()))
                         }
-                        _ => {
-                            self.error(
+                        other => {
+                            return Err(self.error(
                                 format!(
-                                    "Cannot compare {:?} and {:?}. Unexpected types.",
-                                    left_stack, right_stack
+                                    "'reverse' expects ARRAY or STR, but got {}!",
+                                    other.type_name()
                                 )
                                 .as_str(),
-                            );
+                            ))
                         }
                     }
 
-                    pc += 1
+                    self.pc += 1;
+                }
+                Operations::ENUMERATE => {
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "enumerate")?;
+
+                    let pairs = array
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, item)| Value::ARRAY(vec![Value::INT(index as i32), item]))
+                        .collect();
+
+                    self.stack.push(Value::ARRAY(pairs));
+                    self.pc += 1;
                 }
-                Operations::EQ => {
-                    let right_stack = self.stack.pop().unwrap();
-                    let left_stack = self.stack.pop().unwrap();
+                Operations::ZIP => {
+                    // pairs are made until the shorter array ends
 
-                    if left_stack == right_stack {
-                        self.stack.push(Value::BOOL(true));
-                    } else {
-                        self.stack.push(Value::BOOL(false));
-                    }
+                    let stack_value = self.pop()?;
+                    let second = self.array(stack_value, "zip")?;
+
+                    let stack_value = self.pop()?;
+                    let first = self.array(stack_value, "zip")?;
 
-                    pc += 1;
+                    let pairs = first
+                        .into_iter()
+                        .zip(second)
+                        .map(|(left, right)| Value::ARRAY(vec![left, right]))
+                        .collect();
+
+                    self.stack.push(Value::ARRAY(pairs));
+                    self.pc += 1;
                 }
-                Operations::ARR => {
-                    let mut array_result = Vec::new();
+                Operations::ANY | Operations::ALL => {
+                    // `any` stops on the first true, `all` on the first false
 
-                    for i in self.stack.clone().iter() {
-                        array_result.push(i.clone());
-                        self.stack.pop();
-                    }
+                    let (name, stop) = match self.program[self.pc] {
+                        Operations::ANY => ("any", true),
+                        _ => ("all", false),
+                    };
 
-                    self.stack.push(Value::ARRAY(array_result));
+                    let function = self.pop()?;
+                    let function = self.callable(function, name)?;
 
-                    pc += 1;
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, name)?;
+
+                    self.start(
+                        function,
+                        TaskState::FIND {
+                            items: array.into_iter(),
+                            name,
+                            stop,
+                        },
+                    )?;
                 }
-                Operations::SLICE => {
-                    // Slice from value at the top of stack
+                Operations::MIN | Operations::MAX => {
+                    // empty array has no smallest or biggest item, so it gives null
 
-                    let stack_argument = self.stack.pop().unwrap();
-                    let slicable_object = self.stack.pop().unwrap();
+                    let (name, wanted) = match self.program[self.pc] {
+                        Operations::MIN => ("min", Ordering::Less),
+                        _ => ("max", Ordering::Greater),
+                    };
 
-                    arg = Operations::ARG(stack_argument);
+                    let stack_value = self.pop()?;
+                    let mut items = self.array(stack_value, name)?.into_iter();
 
-                    match arg {
-                        Operations::ARG(Value::INT(slice_index)) => match slicable_object {
-                            Value::STR(slicable_string) => {
-                                let string_vector = slicable_string.chars().collect::<Vec<_>>();
+                    let mut result = items.next().unwrap_or(Value::NULL);
 
-                                self.stack.push(Value::STR(
-                                    string_vector[slice_index as usize].to_string(),
-                                ));
-                            }
-                            Value::ARRAY(slicable_array) => {
-                                self.stack
-                                    .push(slicable_array[slice_index as usize].clone());
-                            }
-                            Value::INT(slicable_int) => {
-                                let arr = (0..=slicable_int).collect::<Vec<i32>>();
-                                self.stack.push(Value::INT(arr[slice_index as usize]));
-                            }
-                            _ => self
-                                .error("Cannot get slice from any type exclude STR, ARRAY and INT"),
-                        },
-                        _ => {
-                            self.error("Cannot get slice of non-integer index!");
+                    for item in items {
+                        if self.compare(&item, &result)? == Some(wanted) {
+                            result = item;
                         }
+                    }
+
+                    self.stack.push(result);
+                    self.pc += 1;
+                }
+                Operations::SUM => {
+                    let stack_value = self.pop()?;
+                    let array = self.array(stack_value, "sum")?;
+
+                    let mut sum = Value::INT(0);
+
+                    for item in array {
+                        sum = match (sum, item) {
+                            (Value::INT(a), Value::INT(b)) => match a.checked_add(b) {
+                                Some(result) => Value::INT(result),
+                                None => return Err(self.error("Integer overflow while adding!")),
+                            },
+                            (Value::INT(a), Value::FLOAT(b)) => Value::FLOAT(a as f64 + b),
+                            (Value::FLOAT(a), Value::INT(b)) => Value::FLOAT(a + b as f64),
+                            (Value::FLOAT(a), Value::FLOAT(b)) => Value::FLOAT(a + b),
+                            (_, other) => {
+                                return Err(self.error(
+                                    format!(
+                                        "'sum' expects numbers, but got {}!",
+                                        other.type_name()
+                                    )
+                                    .as_str(),
+                                ))
+                            }
+                        };
+                    }
+
+                    self.stack.push(sum);
+                    self.pc += 1;
+                }
+                Operations::SLICE_RANGE => {
+                    // items from `start` up to `end` (not including it)
+
+                    let (start, end) = match (self.pop()?, self.pop()?) {
+                        (Value::INT(end), Value::INT(start)) => (start, end),
+                        _ => return Err(self.error("'slice' bounds must be INT!")),
                     };
 
-                    pc += 1;
+                    match self.pop()? {
+                        Value::ARRAY(array) => {
+                            let (start, end) = self.slice_bounds(start, end, array.len())?;
+                            self.stack.push(Value::ARRAY(array[start..end].to_vec()));
+                        }
+                        Value::STR(string) => {
+                            let length = string.chars().count();
+                            let (start, end) = self.slice_bounds(start, end, length)?;
+
+                            self.stack.push(Value::STR(
+                                string.chars().skip(start).take(end - start).collect(),
+                            ));
+                        }
+                        other => {
+                            return Err(self.error(
+                                format!(
+                                    "'slice' expects ARRAY or STR, but got {}!",
+                                    other.type_name()
+                                )
+                                .as_str(),
+                            ))
+                        }
+                    }
+
+                    self.pc += 1;
                 }
                 Operations::HALT => break,
-                _ => {
-                    eprintln!("Undefined operation: {:?}!", &self.program[pc]);
-                    pc += 1
-                }
             }
         }
 
         return Ok(());
     }
 }
+
+// slots are allocated when variable is assigned first time
+
+// count of operands taken from the stack by operations which change container
+
+pub fn change_operands(operation: Operations) -> Option<usize> {
+    match operation {
+        Operations::ARR_POP => Some(0),
+        Operations::ARR_PUSH | Operations::REMOVE => Some(1),
+        Operations::ARR_INSERT | Operations::SLICE_SET => Some(2),
+        _ => None,
+    }
+}
+
+fn set_slot(slots: &mut Vec<Option<Value>>, slot: u32, value: Value) {
+    let slot = slot as usize;
+
+    if slot >= slots.len() {
+        slots.resize(slot + 1, None);
+    }
+
+    slots[slot] = Some(value);
+}